@@ -1,14 +1,13 @@
 extern crate test;
 use itertools::Itertools;
-use std::{collections::HashSet, fs};
+use std::cmp::Reverse;
+use std::fs;
 
 #[cfg(test)]
 use test::Bencher;
 
 type Code = Vec<Vec<char>>;
 
-const LEFT_BRACKETS: [char; 4] = ['{', '[', '(', '<'];
-
 fn input1() -> std::io::Result<String> {
     fs::read_to_string("./src/day10/input.txt")
 }
@@ -20,89 +19,273 @@ fn parse_input(input: &str) -> Code {
         .collect_vec()
 }
 
-fn score_illegal(c: &char) -> usize {
-    match c {
-        ')' => 3,
-        ']' => 57,
-        '}' => 1197,
-        '>' => 25137,
-        _ => panic!("unknown closing bracket"),
-    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pair {
+    open: char,
+    close: char,
+    // Score for a corrupted line stopping at this closer
+    corrupted_score: usize,
+    // Per-character score when this closer completes a line
+    completion_score: usize,
 }
 
-fn get_matching(c: &char) -> char {
-    match c {
-        '(' => ')',
-        '{' => '}',
-        '[' => ']',
-        '<' => '>',
-        _ => panic!("unknown opening bracket"),
+struct BracketTable {
+    pairs: Vec<Pair>,
+}
+
+impl BracketTable {
+    fn standard() -> Self {
+        let pair = |open, close, corrupted_score, completion_score| Pair {
+            open,
+            close,
+            corrupted_score,
+            completion_score,
+        };
+        BracketTable {
+            pairs: vec![
+                pair('(', ')', 3, 1),
+                pair('[', ']', 57, 2),
+                pair('{', '}', 1197, 3),
+                pair('<', '>', 25137, 4),
+            ],
+        }
+    }
+
+    fn opening(&self, c: char) -> Option<&Pair> {
+        self.pairs.iter().find(|p| p.open == c)
+    }
+
+    fn closing(&self, c: char) -> Option<&Pair> {
+        self.pairs.iter().find(|p| p.close == c)
     }
 }
 
-fn part1(input: &Code) -> usize {
-    let left_brackets = HashSet::from(LEFT_BRACKETS);
-    let mut stack = Vec::new();
-    let mut illegals = Vec::new();
-
-    for line in input {
-        stack.clear();
-        for c in line {
-            if left_brackets.contains(c) {
-                stack.push(c)
-            } else {
-                let left = stack.pop().unwrap();
-                if &get_matching(left) != c {
-                    illegals.push(c);
-                    break;
+#[derive(Debug, Clone, PartialEq)]
+enum LineResult {
+    Ok,
+    // Columns are 1-based; expected is None for a closer with nothing open
+    Corrupted {
+        column: usize,
+        expected: Option<char>,
+        found: char,
+    },
+    // Closers needed to finish the line, innermost first
+    Incomplete {
+        missing: String,
+    },
+    UnknownChar {
+        column: usize,
+        found: char,
+    },
+}
+
+fn check_line(table: &BracketTable, line: &[char]) -> LineResult {
+    let mut stack: Vec<&Pair> = Vec::new();
+    for (i, c) in line.iter().enumerate() {
+        if let Some(pair) = table.opening(*c) {
+            stack.push(pair);
+        } else if table.closing(*c).is_some() {
+            match stack.pop() {
+                Some(open) if open.close == *c => (),
+                open => {
+                    return LineResult::Corrupted {
+                        column: i + 1,
+                        expected: open.map(|p| p.close),
+                        found: *c,
+                    }
                 }
             }
+        } else {
+            return LineResult::UnknownChar {
+                column: i + 1,
+                found: *c,
+            };
         }
     }
-    illegals.into_iter().map(score_illegal).sum()
+    match stack.is_empty() {
+        true => LineResult::Ok,
+        false => LineResult::Incomplete {
+            missing: stack.iter().rev().map(|p| p.close).collect(),
+        },
+    }
+}
+
+fn check(table: &BracketTable, input: &Code) -> Vec<LineResult> {
+    input.iter().map(|line| check_line(table, line)).collect()
 }
 
-fn score_remaining(c: &char) -> usize {
-    match c {
-        '(' => 1,
-        '[' => 2,
-        '{' => 3,
-        '<' => 4,
-        _ => panic!(),
+fn score(table: &BracketTable, result: &LineResult) -> Option<usize> {
+    match result {
+        LineResult::Corrupted { found, .. } => Some(table.closing(*found).unwrap().corrupted_score),
+        LineResult::Incomplete { missing } => Some(missing.chars().fold(0, |acc, c| {
+            acc * 5 + table.closing(c).unwrap().completion_score
+        })),
+        LineResult::Ok | LineResult::UnknownChar { .. } => None,
     }
 }
 
-fn part2(input: &Code) -> usize {
-    let left_brackets = HashSet::from(LEFT_BRACKETS);
-    let mut stack = Vec::new();
-    let mut scores = Vec::new();
-
-    for line in input {
-        let mut do_score = true;
-        stack.clear();
-        for c in line {
-            if left_brackets.contains(c) {
-                stack.push(c)
-            } else {
-                let left = stack.pop().unwrap();
-                if &get_matching(left) != c {
-                    do_score = false;
-                    break;
+#[derive(Debug, Clone, PartialEq)]
+enum Edit {
+    // Columns are 1-based positions in the line as it is when the edit applies
+    Replace { column: usize, with: char },
+    Delete { column: usize },
+    Insert { column: usize, c: char },
+    Append(String),
+}
+
+fn apply_edit(line: &[char], edit: &Edit) -> Vec<char> {
+    let mut out = line.to_vec();
+    match edit {
+        Edit::Replace { column, with } => out[column - 1] = *with,
+        Edit::Delete { column } => {
+            out.remove(column - 1);
+        }
+        Edit::Insert { column, c } => out.insert(column - 1, *c),
+        Edit::Append(s) => out.extend(s.chars()),
+    }
+    out
+}
+
+fn completion(result: &LineResult) -> Option<&str> {
+    match result {
+        LineResult::Incomplete { missing } => Some(missing),
+        _ => None,
+    }
+}
+
+// How far a line gets before its first error, then how little it is
+// missing; larger is better
+fn progress(table: &BracketTable, line: &[char]) -> (usize, Reverse<usize>) {
+    match check_line(table, line) {
+        LineResult::Corrupted { column, .. } | LineResult::UnknownChar { column, .. } => {
+            (column, Reverse(0))
+        }
+        LineResult::Incomplete { missing } => (usize::MAX, Reverse(missing.len())),
+        LineResult::Ok => (usize::MAX, Reverse(0)),
+    }
+}
+
+// Edits that make the line valid: each error gets the single edit (the
+// expected closer in its place, dropping it, or inserting the expected closer
+// before it) that makes the most progress, then the line is completed
+fn repair(table: &BracketTable, line: &[char]) -> Vec<Edit> {
+    let mut line = line.to_vec();
+    let mut edits = Vec::new();
+    loop {
+        let candidates = match check_line(table, &line) {
+            LineResult::Ok => break,
+            LineResult::Incomplete { missing } => {
+                edits.push(Edit::Append(missing));
+                break;
+            }
+            LineResult::UnknownChar { column, .. } => vec![Edit::Delete { column }],
+            LineResult::Corrupted {
+                column, expected, ..
+            } => {
+                let mut candidates = Vec::new();
+                if let Some(c) = expected {
+                    candidates.push(Edit::Replace { column, with: c });
+                }
+                candidates.push(Edit::Delete { column });
+                if let Some(c) = expected {
+                    candidates.push(Edit::Insert { column, c });
                 }
+                candidates
             }
+        };
+        // max_by_key keeps the last maximum, so reverse to prefer earlier ones
+        let edit = candidates
+            .into_iter()
+            .rev()
+            .max_by_key(|e| progress(table, &apply_edit(&line, e)))
+            .unwrap();
+        line = apply_edit(&line, &edit);
+        edits.push(edit);
+    }
+    edits
+}
+
+// Unified diff of the input against the repaired lines, with 3 lines of
+// context. Leading whitespace is kept, trailing whitespace dropped on
+// changed lines.
+fn repair_diff(table: &BracketTable, input: &str, path: &str) -> String {
+    const CONTEXT: usize = 3;
+    let original: Vec<&str> = input.lines().collect();
+    let repaired: Vec<String> = original
+        .iter()
+        .map(|raw| {
+            let trimmed = raw.trim();
+            let mut line = trimmed.chars().collect_vec();
+            for edit in repair(table, &line) {
+                line = apply_edit(&line, &edit);
+            }
+            let indent = &raw[..raw.len() - raw.trim_start().len()];
+            match line.iter().copied().eq(trimmed.chars()) {
+                true => raw.to_string(),
+                false => format!("{}{}", indent, line.iter().collect::<String>()),
+            }
+        })
+        .collect();
+
+    let changed: Vec<usize> = (0..original.len())
+        .filter(|&i| original[i] != repaired[i])
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut i = 0;
+    while i < changed.len() {
+        let start = changed[i].saturating_sub(CONTEXT);
+        let mut j = i;
+        while j + 1 < changed.len() && changed[j + 1] - changed[j] <= 2 * CONTEXT + 1 {
+            j += 1;
         }
-        if do_score {
-            scores.push(
-                stack
-                    .iter()
-                    .rev()
-                    .fold(0, |acc, v| acc * 5 + score_remaining(*v)),
-            );
+        let end = (changed[j] + CONTEXT + 1).min(original.len());
+        let len = end - start;
+        out += &format!("@@ -{},{} +{},{} @@\n", start + 1, len, start + 1, len);
+        let mut line = start;
+        while line < end {
+            if original[line] == repaired[line] {
+                out += &format!(" {}\n", original[line]);
+                line += 1;
+                continue;
+            }
+            let run_end = (line..end)
+                .find(|&k| original[k] == repaired[k])
+                .unwrap_or(end);
+            for l in &original[line..run_end] {
+                out += &format!("-{}\n", l);
+            }
+            for l in &repaired[line..run_end] {
+                out += &format!("+{}\n", l);
+            }
+            line = run_end;
         }
+        i = j + 1;
     }
+    out
+}
 
-    scores.sort();
-    scores[(scores.len() / 2)]
+fn part1(input: &Code) -> usize {
+    let table = BracketTable::standard();
+    check(&table, input)
+        .iter()
+        .filter(|r| matches!(r, LineResult::Corrupted { .. }))
+        .filter_map(|r| score(&table, r))
+        .sum()
+}
+
+fn part2(input: &Code) -> usize {
+    let table = BracketTable::standard();
+    let scores: Vec<usize> = check(&table, input)
+        .iter()
+        .filter(|r| matches!(r, LineResult::Incomplete { .. }))
+        .filter_map(|r| score(&table, r))
+        .sorted_unstable()
+        .collect();
+    scores[scores.len() / 2]
 }
 
 pub fn main() -> std::io::Result<()> {
@@ -136,6 +319,159 @@ fn task() {
     assert_eq!(part2(&input), 2377613374);
 }
 
+#[test]
+fn diagnostics() {
+    let table = BracketTable::standard();
+    let line = |s: &str| check_line(&table, &s.chars().collect_vec());
+    assert_eq!(
+        line("{([(<{}[<>[]}>{[]{[(<()>"),
+        LineResult::Corrupted {
+            column: 13,
+            expected: Some(']'),
+            found: '}'
+        }
+    );
+    assert_eq!(
+        line("[({(<(())[]>[[{[]{<()<>>"),
+        LineResult::Incomplete {
+            missing: "}}]])})]".to_string()
+        }
+    );
+    assert_eq!(
+        line("([]){()}[{}])"),
+        LineResult::Corrupted {
+            column: 13,
+            expected: None,
+            found: ')'
+        }
+    );
+    assert_eq!(line("(<>)"), LineResult::Ok);
+    assert_eq!(
+        line("(a)"),
+        LineResult::UnknownChar {
+            column: 2,
+            found: 'a'
+        }
+    );
+
+    // A different syntax with its own pairs and scores
+    let table = BracketTable {
+        pairs: vec![
+            Pair {
+                open: '/',
+                close: '\\',
+                corrupted_score: 10,
+                completion_score: 1,
+            },
+            Pair {
+                open: '(',
+                close: ')',
+                corrupted_score: 20,
+                completion_score: 2,
+            },
+        ],
+    };
+    let input = parse_input("/(\\)\n/(()\n<>");
+    let results = check(&table, &input);
+    assert_eq!(
+        results[0],
+        LineResult::Corrupted {
+            column: 3,
+            expected: Some(')'),
+            found: '\\'
+        }
+    );
+    assert_eq!(score(&table, &results[0]), Some(10));
+    assert_eq!(score(&table, &results[1]), Some(2 * 5 + 1));
+    assert_eq!(
+        results[2],
+        LineResult::UnknownChar {
+            column: 1,
+            found: '<'
+        }
+    );
+}
+
+#[test]
+fn repairs() {
+    let table = BracketTable::standard();
+    let chars = |s: &str| s.chars().collect_vec();
+    let result = check_line(&table, &chars("[({(<(())[]>[[{[]{<()<>>"));
+    assert_eq!(completion(&result), Some("}}]])})]"));
+
+    // Replacing the wrong closer is enough here
+    assert_eq!(
+        repair(&table, &chars("(<>]")),
+        vec![Edit::Replace {
+            column: 4,
+            with: ')'
+        }]
+    );
+    // A stray closer is dropped
+    assert_eq!(
+        repair(&table, &chars("(<>)]")),
+        vec![Edit::Delete { column: 5 }]
+    );
+    // A missing closer is inserted, with the rest completed
+    assert_eq!(
+        repair(&table, &chars("([<>)")),
+        vec![Edit::Insert { column: 5, c: ']' }]
+    );
+    assert_eq!(
+        repair(&table, &chars("{([(<{}[<>[]}>{[]{[(<()>")),
+        vec![
+            Edit::Replace {
+                column: 13,
+                with: ']'
+            },
+            Edit::Append(")]}})])}".to_string())
+        ]
+    );
+
+    // Every repaired example line checks out
+    let input = "[({(<(())[]>[[{[]{<()<>>
+        [(()[<>])]({[<{<<[]>>(
+        {([(<{}[<>[]}>{[]{[(<()>
+        (((({<>}<{<{<>}{[]{[]{}
+        [[<[([]))<([[{}[[()]]]
+        [{[{({}]{}}([{[{{{}}([]
+        {<[[]]>}<{[{[{[]{()[[[]
+        [<(<(<(<{}))><([]([]()
+        <{([([[(<>()){}]>(<<{{
+        <{([{{}}[<[[[<>{}]]]>[]]";
+    for line in parse_input(input) {
+        let mut fixed = line.clone();
+        for edit in repair(&table, &line) {
+            fixed = apply_edit(&fixed, &edit);
+        }
+        assert_eq!(check_line(&table, &fixed), LineResult::Ok);
+    }
+
+    let input = "()\n[]\n(]\n{}\n<>\n()\n()\n()\n()\n()\n()\n<<\n()";
+    assert_eq!(
+        repair_diff(&table, input, "input.txt"),
+        "--- a/input.txt
++++ b/input.txt
+@@ -1,6 +1,6 @@
+ ()
+ []
+-(]
++()
+ {}
+ <>
+ ()
+@@ -9,5 +9,5 @@
+ ()
+ ()
+ ()
+-<<
++<<>>
+ ()
+"
+    );
+    assert_eq!(repair_diff(&table, "()\n<>", "input.txt"), "");
+}
+
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
@@ -1,5 +1,6 @@
 extern crate test;
 use ndarray::Array2;
+use std::collections::HashMap;
 use std::fs;
 
 #[cfg(test)]
@@ -32,64 +33,201 @@ fn parse_input(input: &str) -> Floor {
     floor
 }
 
-fn increment_neighbors(floor: &mut Floor, count: &mut usize) {
-    let floor_clone = floor.clone();
-    let idxs = floor_clone
-        .indexed_iter()
-        .filter(|(_, v)| **v == 9)
-        .map(|(idx, _)| idx);
-
-    const ADJACENTS: &[(usize, usize); 8] = &[
-        (1, 0),
-        (usize::MAX, 0),
-        (0, 1),
-        (0, usize::MAX),
-        (1, 1),
-        (usize::MAX, usize::MAX),
-        (1, usize::MAX),
-        (usize::MAX, 1),
-    ];
-    for i in idxs {
-        for dir in ADJACENTS {
-            let adj_idx = (i.0.wrapping_add(dir.0), i.1.wrapping_add(dir.1));
-            if let Some(v) = floor.get_mut(adj_idx) {
-                if *v < 9 {
-                    *v += 1;
-                }
-            }
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Neighbourhood {
+    // The four orthogonal neighbours
+    VonNeumann,
+    // All eight surrounding cells
+    Moore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Boundary {
+    // Cells past the edge do not exist
+    Fixed,
+    // The grid wraps around in both directions
+    Toroidal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rules {
+    // A cell flashes once its energy goes above this
+    threshold: u32,
+    neighbourhood: Neighbourhood,
+    boundary: Boundary,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            threshold: 9,
+            neighbourhood: Neighbourhood::Moore,
+            boundary: Boundary::Fixed,
         }
-        floor[i] = 10;
-        *count += 1;
     }
 }
 
-fn _part1(input: &Floor, steps: usize, find_all: bool) -> usize {
-    let mut oct = input.clone();
+type Index = (usize, usize);
 
-    let mut count = 0;
-    let mut prev_count = 0;
+#[derive(Debug, Clone)]
+struct Automaton {
+    grid: Floor,
+    rules: Rules,
+    steps: usize,
+}
 
-    for i in 0..steps {
-        increment_neighbors(&mut oct, &mut count);
-        while prev_count < count {
-            prev_count = count.clone();
-            increment_neighbors(&mut oct, &mut count);
+impl Automaton {
+    fn new(grid: &Floor, rules: Rules) -> Self {
+        Automaton {
+            grid: grid.clone(),
+            rules,
+            steps: 0,
         }
-        oct.mapv_inplace(|v| if v == 10 { 0 } else { v + 1 });
-        if find_all & oct.iter().all(|v| *v == 0) {
-            return i + 1;
+    }
+
+    fn neighbours(&self, (r, c): Index) -> Vec<Index> {
+        const VON_NEUMANN: &[(isize, isize)] = &[(1, 0), (-1, 0), (0, 1), (0, -1)];
+        const MOORE: &[(isize, isize)] = &[
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+        ];
+        let dirs = match self.rules.neighbourhood {
+            Neighbourhood::VonNeumann => VON_NEUMANN,
+            Neighbourhood::Moore => MOORE,
+        };
+        let (n_rows, n_cols) = self.grid.dim();
+        dirs.iter()
+            .filter_map(|(dr, dc)| match self.rules.boundary {
+                Boundary::Fixed => {
+                    let next = (r.checked_add_signed(*dr)?, c.checked_add_signed(*dc)?);
+                    (next.0 < n_rows && next.1 < n_cols).then_some(next)
+                }
+                Boundary::Toroidal => Some((
+                    (r as isize + dr).rem_euclid(n_rows as isize) as usize,
+                    (c as isize + dc).rem_euclid(n_cols as isize) as usize,
+                )),
+            })
+            .collect()
+    }
+
+    // Every cell gains one energy, then flashes cascade through a work queue
+    // with each cell flashing at most once. Returns the cells that flashed.
+    fn step(&mut self) -> Vec<Index> {
+        let threshold = self.rules.threshold;
+        self.grid.mapv_inplace(|v| v + 1);
+        let mut flashed: Vec<Index> = self
+            .grid
+            .indexed_iter()
+            .filter(|(_, v)| **v > threshold)
+            .map(|(idx, _)| idx)
+            .collect();
+        let mut has_flashed = self.grid.map(|v| *v > threshold);
+
+        let mut i = 0;
+        while i < flashed.len() {
+            for next in self.neighbours(flashed[i]) {
+                self.grid[next] += 1;
+                if self.grid[next] > threshold && !has_flashed[next] {
+                    has_flashed[next] = true;
+                    flashed.push(next);
+                }
+            }
+            i += 1;
+        }
+        for idx in &flashed {
+            self.grid[*idx] = 0;
+        }
+        self.steps += 1;
+        flashed
+    }
+
+    fn run(&mut self, steps: usize) -> usize {
+        (0..steps).map(|_| self.step().len()).sum()
+    }
+
+    // Step number on which every cell flashes together. Keeps going for as
+    // long as that takes, so only use it on grids that do synchronise; history
+    // tells whether they ever will.
+    fn run_until_all_flash(&mut self) -> usize {
+        let cells = self.grid.len();
+        while self.step().len() != cells {}
+        self.steps
+    }
+
+    // Steps until the grid returns to a state it was in before. The state space
+    // is finite, so this always ends, whether or not the cells ever synchronise.
+    fn history(&mut self) -> History {
+        let mut seen = HashMap::new();
+        let mut steps = Vec::new();
+        let cycle = loop {
+            if let Some(&start) = seen.get(&self.grid) {
+                break Cycle {
+                    pre_period: start,
+                    period: self.steps - start,
+                };
+            }
+            seen.insert(self.grid.clone(), self.steps);
+            let flashed = self.step();
+            steps.push(StepRecord {
+                step: self.steps,
+                flashed,
+            });
+        };
+        History {
+            cells: self.grid.len(),
+            steps,
+            cycle,
         }
     }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct StepRecord {
+    step: usize,
+    flashed: Vec<Index>,
+}
 
-    count
+// The grid is in the same state after pre_period + k * period steps for any k
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cycle {
+    pre_period: usize,
+    period: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct History {
+    cells: usize,
+    steps: Vec<StepRecord>,
+    cycle: Cycle,
+}
+
+impl History {
+    fn flash_counts(&self) -> Vec<usize> {
+        self.steps.iter().map(|s| s.flashed.len()).collect()
+    }
+
+    // Everything after the cycle closes repeats what is recorded here, so a
+    // synchronised flash that is not in the history never happens.
+    fn first_synchronised(&self) -> Option<usize> {
+        self.steps
+            .iter()
+            .find(|s| s.flashed.len() == self.cells)
+            .map(|s| s.step)
+    }
 }
 
 fn part1(input: &Floor) -> usize {
-    _part1(input, 100, false)
+    Automaton::new(input, Rules::default()).run(100)
 }
 
 fn part2(input: &Floor) -> usize {
-    _part1(input, 999, true)
+    Automaton::new(input, Rules::default()).run_until_all_flash()
 }
 
 pub fn main() -> std::io::Result<()> {
@@ -107,7 +245,7 @@ fn example_mini() {
     19991
     11111";
     let input = parse_input(input);
-    assert_eq!(_part1(&input, 2, false), 9);
+    assert_eq!(Automaton::new(&input, Rules::default()).run(2), 9);
 }
 
 #[test]
@@ -134,6 +272,117 @@ fn task() {
     assert_eq!(part2(&input), 249);
 }
 
+#[test]
+fn rules() {
+    let input = parse_input(
+        "5483143223
+    2745854711
+    5264556173
+    6141336146
+    6357385478
+    4167524645
+    2176841721
+    6882881134
+    4846848554
+    5283751526",
+    );
+    let mut automaton = Automaton::new(&input, Rules::default());
+    assert_eq!(automaton.run(10), 204);
+    assert_eq!(automaton.steps, 10);
+
+    // A single charged cell in the corner reaches across the edges when the
+    // grid wraps
+    let input = parse_input("1119\n1111\n1111");
+    let run = |rules| {
+        let mut automaton = Automaton::new(&input, rules);
+        let flashed = automaton.step();
+        (flashed.len(), automaton.grid)
+    };
+    let (flashes, grid) = run(Rules::default());
+    assert_eq!(flashes, 1);
+    assert_eq!(grid, parse_input("2230\n2233\n2222"));
+    let (flashes, grid) = run(Rules {
+        boundary: Boundary::Toroidal,
+        ..Rules::default()
+    });
+    assert_eq!(flashes, 1);
+    assert_eq!(grid, parse_input("3230\n3233\n3233"));
+    let (_, grid) = run(Rules {
+        neighbourhood: Neighbourhood::VonNeumann,
+        ..Rules::default()
+    });
+    assert_eq!(grid, parse_input("2230\n2223\n2222"));
+    // Higher thresholds hold the charge
+    let (flashes, grid) = run(Rules {
+        threshold: 10,
+        ..Rules::default()
+    });
+    assert_eq!(flashes, 0);
+    assert_eq!(grid[(0, 3)], 10);
+}
+
+#[test]
+fn history() {
+    let input = parse_input(
+        "5483143223
+    2745854711
+    5264556173
+    6141336146
+    6357385478
+    4167524645
+    2176841721
+    6882881134
+    4846848554
+    5283751526",
+    );
+    let history = Automaton::new(&input, Rules::default()).history();
+    // Once synchronised, the grid repeats every ten steps
+    assert_eq!(
+        history.cycle,
+        Cycle {
+            pre_period: 195,
+            period: 10
+        }
+    );
+    assert_eq!(history.steps.len(), 205);
+    assert_eq!(history.flash_counts()[..10].iter().sum::<usize>(), 204);
+    assert_eq!(history.first_synchronised(), Some(195));
+
+    // Two cells that keep flashing out of step
+    let history = Automaton::new(&parse_input("05"), Rules::default()).history();
+    assert_eq!(
+        history.cycle,
+        Cycle {
+            pre_period: 0,
+            period: 9
+        }
+    );
+    assert_eq!(history.flash_counts(), [0, 0, 0, 0, 1, 0, 0, 0, 1]);
+    assert_eq!(
+        history.steps[4],
+        StepRecord {
+            step: 5,
+            flashed: vec![(0, 1)]
+        }
+    );
+    assert_eq!(history.steps[8].flashed, [(0, 0)]);
+    assert_eq!(history.first_synchronised(), None);
+
+    let rules = Rules {
+        neighbourhood: Neighbourhood::VonNeumann,
+        ..Rules::default()
+    };
+    let history = Automaton::new(&parse_input("012\n345"), rules).history();
+    assert_eq!(
+        history.cycle,
+        Cycle {
+            pre_period: 7,
+            period: 8
+        }
+    );
+    assert_eq!(history.first_synchronised(), None);
+}
+
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
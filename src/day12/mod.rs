@@ -1,6 +1,9 @@
 extern crate test;
+use petgraph::dot::{Config, Dot};
+use petgraph::graph::{NodeIndex, UnGraph};
+use petgraph::visit::EdgeRef;
 use std::collections::HashMap;
-use std::collections::VecDeque;
+use std::fmt;
 use std::fs;
 
 #[cfg(test)]
@@ -25,46 +28,447 @@ fn parse_input(input: &str) -> CaveMap {
     map
 }
 
-fn parts(input: &CaveMap<'_>, skip_dupe: bool) -> usize {
-    let mut q = VecDeque::from([(vec!["end"], false)]);
+#[derive(Debug, PartialEq)]
+enum GraphError {
+    MissingCave(String),
+    AdjacentBigCaves(String, String),
+    StartIsEnd(String),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingCave(name) => write!(f, "cave {:?} does not appear in the map", name),
+            Self::AdjacentBigCaves(a, b) => write!(
+                f,
+                "big caves {:?} and {:?} are adjacent, so there are infinitely many paths",
+                a, b
+            ),
+            Self::StartIsEnd(name) => write!(f, "start and end are both {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+// The cave map with every cave interned to an index into `names`. The same
+// index is the cave's node in `graph`.
+#[derive(Debug)]
+struct Caves<'a> {
+    names: Vec<&'a str>,
+    ids: HashMap<&'a str, usize>,
+    adjacent: Vec<Vec<usize>>,
+    small: Vec<bool>,
+    graph: UnGraph<&'a str, ()>,
+}
+
+impl<'a> Caves<'a> {
+    fn new(map: &CaveMap<'a>) -> Self {
+        let mut names: Vec<&str> = map.keys().copied().collect();
+        names.sort_unstable();
+        let ids: HashMap<&str, usize> = names.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+        let adjacent: Vec<Vec<usize>> = names
+            .iter()
+            .map(|name| map[name].iter().map(|n| ids[n]).collect())
+            .collect();
+        let small = names.iter().map(|name| !is_all_upper(name)).collect();
+
+        let mut graph = UnGraph::with_capacity(names.len(), 0);
+        for name in &names {
+            graph.add_node(*name);
+        }
+        for (i, next) in adjacent.iter().enumerate() {
+            for &j in next.iter().filter(|&&j| i <= j) {
+                graph.add_edge(NodeIndex::new(i), NodeIndex::new(j), ());
+            }
+        }
+        Caves {
+            names,
+            ids,
+            adjacent,
+            small,
+            graph,
+        }
+    }
+
+    // Graphviz source with big caves as boxes and small caves as ellipses
+    fn to_dot(&self) -> String {
+        let node_attributes = |_, (i, name): (NodeIndex, &&str)| {
+            let shape = if self.small[i.index()] {
+                "shape = ellipse"
+            } else {
+                "shape = box, style = bold"
+            };
+            format!("label = \"{}\", {}", name, shape)
+        };
+        // Labels come from the attribute getter, so the weights are never printed
+        format!(
+            "{:?}",
+            Dot::with_attr_getters(
+                &self.graph,
+                &[Config::NodeNoLabel, Config::EdgeNoLabel],
+                &|_, _| String::new(),
+                &node_attributes,
+            )
+        )
+    }
+
+    fn id(&self, name: &str) -> Result<usize, GraphError> {
+        self.ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| GraphError::MissingCave(name.to_string()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VisitPolicy<'a> {
+    start: &'a str,
+    end: &'a str,
+    // Visits allowed to each small cave
+    max_visits: u32,
+    // How many small caves may go over max_visits, and how far
+    exceptions: usize,
+    exception_visits: u32,
+    // Caves that may not be entered at all
+    forbidden: Vec<&'a str>,
+    // Caves every path has to pass through
+    required: Vec<&'a str>,
+}
+
+impl Default for VisitPolicy<'_> {
+    fn default() -> Self {
+        VisitPolicy {
+            start: "start",
+            end: "end",
+            max_visits: 1,
+            exceptions: 0,
+            exception_visits: 1,
+            forbidden: Vec::new(),
+            required: Vec::new(),
+        }
+    }
+}
+
+// A policy resolved against the interned caves, together with the visit
+// counts of the path being built. The start is never re-entered and the end
+// finishes the path, so only the small caves in between have visit limits.
+struct Walk<'c, 'a> {
+    caves: &'c Caves<'a>,
+    rules: Vec<CaveRule>,
+    max_visits: u32,
+    exceptions: usize,
+    exception_visits: u32,
+    start: usize,
+    end: usize,
+    counts: Vec<u32>,
+    exceeded: usize,
+    // Required caves not on the path yet
+    unseen: usize,
+    // The search state packed into one word while it fits
+    packed: Option<u128>,
+    // What to take off the counters of caves with no visits left to bring
+    // them down to max_visits
+    spent: u128,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct CaveRule {
+    blocked: bool,
+    // Small caves other than start and end, which have visit limits
+    limited: bool,
+    required: bool,
+    // Where the cave's counter, or seen bit for other required caves, lives in
+    // the packed state
+    offset: Option<u32>,
+}
+
+// Once every exception is used, a cave with no visits left is as closed as
+// one at max_visits, so both are keyed as max_visits to share memo entries.
+// Required caves keep their count, since with max_visits 0 the rewrite would
+// also forget that they were seen.
+#[derive(Debug, PartialEq)]
+enum State {
+    Packed(usize, u128, bool),
+    // The cave and exhausted flag, then the counts
+    Wide(Vec<u32>),
+}
+
+impl<'c, 'a> Walk<'c, 'a> {
+    fn new(caves: &'c Caves<'a>, policy: &VisitPolicy) -> Result<Self, GraphError> {
+        let start = caves.id(policy.start)?;
+        let end = caves.id(policy.end)?;
+        if start == end {
+            return Err(GraphError::StartIsEnd(policy.start.to_string()));
+        }
+        let mut rules = vec![CaveRule::default(); caves.names.len()];
+        rules[start].blocked = true;
+        for name in &policy.forbidden {
+            rules[caves.id(name)?].blocked = true;
+        }
+        // Two big caves next to each other can be walked between forever, unless
+        // one of them is the end, where the path stops, or the start, which is
+        // blocked
+        let open_big = |i: usize| !caves.small[i] && !rules[i].blocked && i != end;
+        for edge in caves.graph.edge_references() {
+            let (a, b) = (edge.source().index(), edge.target().index());
+            if open_big(a) && open_big(b) {
+                return Err(GraphError::AdjacentBigCaves(
+                    caves.names[a].to_string(),
+                    caves.names[b].to_string(),
+                ));
+            }
+        }
+        for name in &policy.required {
+            rules[caves.id(name)?].required = true;
+        }
+        for (i, rule) in rules.iter_mut().enumerate() {
+            rule.limited = caves.small[i] && i != start && i != end;
+        }
+        let mut counts = vec![0; caves.names.len()];
+        counts[start] = 1;
+
+        // Counters only need enough bits for the most visits a cave can take
+        let max_count = policy.max_visits.max(policy.exception_visits);
+        let width = u32::BITS - max_count.leading_zeros();
+        let mut used = 0;
+        for (i, rule) in rules.iter_mut().enumerate() {
+            let bits = match (rule.limited, rule.required) {
+                (true, _) => width,
+                (false, true) if i != start && i != end => 1,
+                _ => continue,
+            };
+            rule.offset = Some(used);
+            used += bits;
+        }
+        let packed = (used <= u128::BITS).then_some(0);
+        let unseen = (0..caves.names.len())
+            .filter(|&i| rules[i].required && counts[i] == 0)
+            .count();
+
+        Ok(Walk {
+            caves,
+            rules,
+            max_visits: policy.max_visits,
+            exceptions: policy.exceptions,
+            exception_visits: policy.exception_visits,
+            start,
+            end,
+            unseen,
+            counts,
+            exceeded: 0,
+            packed,
+            spent: 0,
+        })
+    }
+
+    fn enter(&mut self, cave: usize) -> bool {
+        let rule = self.rules[cave];
+        if rule.blocked {
+            return false;
+        }
+        let visits = self.counts[cave] + 1;
+        if rule.limited && visits > self.max_visits {
+            let new_exception = visits == self.max_visits + 1;
+            if visits > self.exception_visits || (new_exception && self.exceeded == self.exceptions)
+            {
+                return false;
+            }
+            if new_exception {
+                self.exceeded += 1;
+            }
+        }
+        self.counts[cave] = visits;
+        if rule.required && visits == 1 {
+            self.unseen -= 1;
+        }
+        self.update_packed(cave, rule, true);
+        true
+    }
+
+    // Limited caves count up and down, required ones only flip on the first
+    // visit
+    fn update_packed(&mut self, cave: usize, rule: CaveRule, entered: bool) {
+        let (Some(packed), Some(offset)) = (self.packed, rule.offset) else {
+            return;
+        };
+        if !rule.limited && self.counts[cave] != entered as u32 {
+            return;
+        }
+        // The count before leaving, or after entering
+        let count = self.counts[cave] + !entered as u32;
+        let spent = if rule.limited && !rule.required && self.is_spent(count) {
+            ((self.exception_visits - self.max_visits) as u128) << offset
+        } else {
+            0
+        };
+        if entered {
+            self.packed = Some(packed + (1 << offset));
+            self.spent += spent;
+        } else {
+            self.packed = Some(packed - (1 << offset));
+            self.spent -= spent;
+        }
+    }
+
+    fn is_spent(&self, count: u32) -> bool {
+        count > self.max_visits && count == self.exception_visits
+    }
+
+    fn leave(&mut self, cave: usize) {
+        let rule = self.rules[cave];
+        if rule.limited && self.counts[cave] == self.max_visits + 1 {
+            self.exceeded -= 1;
+        }
+        self.counts[cave] -= 1;
+        if rule.required && self.counts[cave] == 0 {
+            self.unseen += 1;
+        }
+        self.update_packed(cave, rule, false);
+    }
+
+    fn complete(&self) -> bool {
+        self.unseen == 0
+    }
+
+    // Everything the rest of the search depends on: visits to limited caves,
+    // and whether each required cave has been seen
+    fn state(&self, cave: usize) -> State {
+        let exhausted = self.exceeded == self.exceptions;
+        if let Some(packed) = self.packed {
+            let packed = if exhausted {
+                packed - self.spent
+            } else {
+                packed
+            };
+            return State::Packed(cave, packed, exhausted);
+        }
+        let counts = self.counts.iter().zip(&self.rules).map(|(&count, rule)| {
+            match (rule.limited, rule.required) {
+                (true, false) if exhausted && self.is_spent(count) => self.max_visits,
+                (true, _) => count,
+                (false, true) => count.min(1),
+                (false, false) => 0,
+            }
+        });
+        let key = [cave as u32, exhausted as u32].into_iter().chain(counts);
+        State::Wide(key.collect())
+    }
+}
+
+// Split by key type so the common packed case hashes a plain tuple
+#[derive(Default)]
+struct Memo {
+    packed: HashMap<(usize, u128, bool), usize>,
+    wide: HashMap<Vec<u32>, usize>,
+}
+
+impl Memo {
+    fn get(&self, state: &State) -> Option<usize> {
+        match state {
+            State::Packed(cave, packed, exhausted) => {
+                self.packed.get(&(*cave, *packed, *exhausted)).copied()
+            }
+            State::Wide(key) => self.wide.get(key).copied(),
+        }
+    }
+
+    fn insert(&mut self, state: State, count: usize) {
+        match state {
+            State::Packed(cave, packed, exhausted) => {
+                self.packed.insert((cave, packed, exhausted), count);
+            }
+            State::Wide(key) => {
+                self.wide.insert(key, count);
+            }
+        }
+    }
+}
+
+fn count_from(walk: &mut Walk, memo: &mut Memo, cave: usize) -> usize {
+    if cave == walk.end {
+        return walk.complete() as usize;
+    }
+    let state = walk.state(cave);
+    if let Some(count) = memo.get(&state) {
+        return count;
+    }
+    let caves = walk.caves;
     let mut count = 0;
+    for &next in &caves.adjacent[cave] {
+        if walk.enter(next) {
+            count += count_from(walk, memo, next);
+            walk.leave(next);
+        }
+    }
+    memo.insert(state, count);
+    count
+}
+
+fn count_paths(caves: &Caves, policy: &VisitPolicy) -> Result<usize, GraphError> {
+    let mut walk = Walk::new(caves, policy)?;
+    let start = walk.start;
+    Ok(count_from(&mut walk, &mut Memo::default(), start))
+}
+
+// Depth-first enumeration of the paths, one at a time
+struct Paths<'c, 'a> {
+    walk: Walk<'c, 'a>,
+    // Each cave on the current path with the index of its next neighbour to try
+    stack: Vec<(usize, usize)>,
+}
 
-    while let Some((current, has_duplicate)) = q.pop_front() {
-        for &neighbor in &input[current.last().unwrap()] {
-            match neighbor {
-                "end" => continue,
-                "start" => count += 1,
-                neighbor => {
-                    let mut has_duplicate = has_duplicate;
-                    if !is_all_upper(neighbor) && current.contains(&neighbor) {
-                        if skip_dupe || has_duplicate {
-                            continue;
-                        }
-                        has_duplicate = true;
-                    }
-                    let mut next = current.clone();
-                    next.push(neighbor);
-                    q.push_back((next, has_duplicate));
+impl<'c, 'a> Iterator for Paths<'c, 'a> {
+    type Item = Vec<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (cave, i) = *self.stack.last()?;
+            let adjacent = &self.walk.caves.adjacent[cave];
+            if cave == self.walk.end || i == adjacent.len() {
+                self.stack.pop();
+                self.walk.leave(cave);
+                continue;
+            }
+            self.stack.last_mut().unwrap().1 += 1;
+            let next = adjacent[i];
+            if self.walk.enter(next) {
+                self.stack.push((next, 0));
+                if next == self.walk.end && self.walk.complete() {
+                    let names = &self.walk.caves.names;
+                    return Some(self.stack.iter().map(|&(c, _)| names[c]).collect());
                 }
             }
         }
     }
-    count
 }
 
-fn part1(input: &CaveMap) -> usize {
-    parts(input, true)
+fn paths<'c, 'a>(caves: &'c Caves<'a>, policy: &VisitPolicy) -> Result<Paths<'c, 'a>, GraphError> {
+    let walk = Walk::new(caves, policy)?;
+    let stack = vec![(walk.start, 0)];
+    Ok(Paths { walk, stack })
 }
 
-fn part2(input: &CaveMap) -> usize {
-    parts(input, false)
+fn part1(caves: &Caves) -> Result<usize, GraphError> {
+    count_paths(caves, &VisitPolicy::default())
+}
+
+fn part2(caves: &Caves) -> Result<usize, GraphError> {
+    let policy = VisitPolicy {
+        exceptions: 1,
+        exception_visits: 2,
+        ..VisitPolicy::default()
+    };
+    count_paths(caves, &policy)
 }
 
 pub fn main() -> std::io::Result<()> {
     let input_str = input1()?;
     let input = parse_input(&input_str);
-    println!("{:?}", part1(&input));
-    println!("{:?}", part2(&input));
+    let caves = Caves::new(&input);
+    let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    println!("{:?}", part1(&caves).map_err(invalid)?);
+    println!("{:?}", part2(&caves).map_err(invalid)?);
     Ok(())
 }
 
@@ -77,9 +481,9 @@ fn example1() {
         b-d
         A-end
         b-end";
-    let input = parse_input(input);
-    assert_eq!(part1(&input), 10);
-    assert_eq!(part2(&input), 36);
+    let input = Caves::new(&parse_input(input));
+    assert_eq!(part1(&input), Ok(10));
+    assert_eq!(part2(&input), Ok(36));
 }
 
 #[test]
@@ -94,9 +498,9 @@ fn example2() {
         kj-sa
         kj-HN
         kj-dc";
-    let input = parse_input(input);
-    assert_eq!(part1(&input), 19);
-    assert_eq!(part2(&input), 103);
+    let input = Caves::new(&parse_input(input));
+    assert_eq!(part1(&input), Ok(19));
+    assert_eq!(part2(&input), Ok(103));
 }
 
 #[test]
@@ -119,25 +523,273 @@ fn example3() {
     zg-he
     pj-fs
     start-RW";
-    let input = parse_input(input);
-    assert_eq!(part1(&input), 226);
-    assert_eq!(part2(&input), 3509);
+    let input = Caves::new(&parse_input(input));
+    assert_eq!(part1(&input), Ok(226));
+    assert_eq!(part2(&input), Ok(3509));
 }
 
 #[test]
 fn task() {
     let input_str = input1().unwrap();
-    let input = parse_input(&input_str);
-    assert_eq!(part1(&input), 4970);
-    assert_eq!(part2(&input), 137948);
+    let input = Caves::new(&parse_input(&input_str));
+    assert_eq!(part1(&input), Ok(4970));
+    assert_eq!(part2(&input), Ok(137948));
+}
+
+#[test]
+fn dense() {
+    // Twelve small caves all linked to each other and to both ends. Every
+    // ordered selection of them is a path, far too many to enumerate one by one.
+    let names: Vec<String> = (0..12).map(|i| format!("c{}", i)).collect();
+    let mut input = String::new();
+    for (i, a) in names.iter().enumerate() {
+        input += &format!("start-{}\n{}-end\n", a, a);
+        for b in &names[i + 1..] {
+            input += &format!("{}-{}\n", a, b);
+        }
+    }
+    let map = parse_input(&input);
+    let caves = Caves::new(&map);
+    assert_eq!(part1(&caves), Ok(1302061344));
+    assert!(part2(&caves).unwrap() > 1302061344);
+}
+
+#[test]
+fn policies() {
+    let map = parse_input(
+        "start-A
+        start-b
+        A-c
+        A-b
+        b-d
+        A-end
+        b-end",
+    );
+    let caves = Caves::new(&map);
+    let default = VisitPolicy::default();
+    let mut found: Vec<String> = paths(&caves, &default)
+        .unwrap()
+        .map(|p| p.join(","))
+        .collect();
+    found.sort();
+    assert_eq!(found.len(), 10);
+    assert_eq!(found[0], "start,A,b,A,c,A,end");
+    assert_eq!(found[9], "start,b,end");
+    // The iterator is lazy, so the first path comes without walking the rest
+    assert_eq!(
+        paths(&caves, &default).unwrap().next().map(|p| p.len() > 2),
+        Some(true)
+    );
+
+    let twice = VisitPolicy {
+        exceptions: 1,
+        exception_visits: 2,
+        ..VisitPolicy::default()
+    };
+    assert_eq!(paths(&caves, &twice).unwrap().count(), 36);
+    // Any number of caves visited twice, but never three times
+    let any_twice = VisitPolicy {
+        max_visits: 2,
+        ..VisitPolicy::default()
+    };
+    let unlimited = VisitPolicy {
+        exceptions: usize::MAX,
+        exception_visits: 2,
+        ..VisitPolicy::default()
+    };
+    assert_eq!(
+        count_paths(&caves, &any_twice),
+        count_paths(&caves, &unlimited)
+    );
+    assert_eq!(
+        count_paths(&caves, &any_twice).unwrap(),
+        paths(&caves, &any_twice).unwrap().count()
+    );
+
+    let no_b = VisitPolicy {
+        forbidden: vec!["b"],
+        ..VisitPolicy::default()
+    };
+    let found: Vec<_> = paths(&caves, &no_b).unwrap().collect();
+    assert_eq!(
+        found,
+        [
+            vec!["start", "A", "c", "A", "end"],
+            vec!["start", "A", "end"]
+        ]
+    );
+    let via_d = VisitPolicy {
+        required: vec!["d"],
+        ..twice
+    };
+    let found: Vec<_> = paths(&caves, &via_d).unwrap().collect();
+    assert_eq!(found.len(), count_paths(&caves, &via_d).unwrap());
+    assert!(found.iter().all(|p| p.contains(&"d")));
+
+    // Reversed direction gives the same paths backwards
+    let reversed = VisitPolicy {
+        start: "end",
+        end: "start",
+        ..VisitPolicy::default()
+    };
+    let mut backwards: Vec<String> = paths(&caves, &reversed)
+        .unwrap()
+        .map(|mut p| {
+            p.reverse();
+            p.join(",")
+        })
+        .collect();
+    backwards.sort();
+    let mut forwards: Vec<String> = paths(&caves, &default)
+        .unwrap()
+        .map(|p| p.join(","))
+        .collect();
+    forwards.sort();
+    assert_eq!(backwards, forwards);
+
+    // With no visits allowed only big caves remain between start and end
+    let none = VisitPolicy {
+        max_visits: 0,
+        ..VisitPolicy::default()
+    };
+    let found: Vec<_> = paths(&caves, &none).unwrap().collect();
+    assert_eq!(found, [vec!["start", "A", "end"]]);
+    assert_eq!(count_paths(&caves, &none), Ok(1));
+
+    let same = VisitPolicy {
+        start: "A",
+        end: "A",
+        ..VisitPolicy::default()
+    };
+    assert_eq!(
+        count_paths(&caves, &same),
+        Err(GraphError::StartIsEnd("A".to_string()))
+    );
+    assert!(paths(&caves, &same).is_err());
+
+    let missing = VisitPolicy {
+        required: vec!["zz"],
+        ..VisitPolicy::default()
+    };
+    assert_eq!(
+        count_paths(&caves, &missing),
+        Err(GraphError::MissingCave("zz".to_string()))
+    );
+}
+
+#[test]
+fn required_with_exceptions() {
+    // No regular visits, so the required cave can only be reached as one of
+    // the exceptions, and its memo key must still record that it was seen
+    let map =
+        parse_input("start-b\nstart-e\nend-a\nend-b\nend-X\na-c\na-e\na-Y\nb-e\nb-Y\nc-d\nd-Y");
+    let caves = Caves::new(&map);
+    let policy = VisitPolicy {
+        max_visits: 0,
+        exceptions: 2,
+        exception_visits: 2,
+        required: vec!["a"],
+        ..VisitPolicy::default()
+    };
+    assert_eq!(count_paths(&caves, &policy), Ok(10));
+    assert_eq!(paths(&caves, &policy).unwrap().count(), 10);
+}
+
+#[test]
+fn wide_state() {
+    // 130 small caves in a chain, with a big cave linking both ends: too many
+    // counters to pack, so the memo falls back to full count vectors
+    let mut input = String::from("start-c0\nc129-end\nX-c0\nX-c129\n");
+    for i in 0..129 {
+        input += &format!("c{}-c{}\n", i, i + 1);
+    }
+    let map = parse_input(&input);
+    let caves = Caves::new(&map);
+    let walk = Walk::new(&caves, &VisitPolicy::default()).unwrap();
+    assert_eq!(walk.packed, None);
+
+    let twice = VisitPolicy {
+        exceptions: 1,
+        exception_visits: 2,
+        ..VisitPolicy::default()
+    };
+    for (policy, expected) in [(VisitPolicy::default(), 2), (twice, 8)] {
+        assert_eq!(count_paths(&caves, &policy), Ok(expected));
+        assert_eq!(paths(&caves, &policy).unwrap().count(), expected);
+    }
+}
+
+#[test]
+fn graph() {
+    let map = parse_input(
+        "start-A
+        start-b
+        A-c
+        A-b
+        b-d
+        A-end
+        b-end",
+    );
+    let caves = Caves::new(&map);
+    assert_eq!(caves.graph.node_count(), 6);
+    assert_eq!(caves.graph.edge_count(), 7);
+    assert_eq!(
+        caves.to_dot(),
+        r#"graph {
+    0 [ label = "A", shape = box, style = bold]
+    1 [ label = "b", shape = ellipse]
+    2 [ label = "c", shape = ellipse]
+    3 [ label = "d", shape = ellipse]
+    4 [ label = "end", shape = ellipse]
+    5 [ label = "start", shape = ellipse]
+    0 -- 5 [ ]
+    0 -- 2 [ ]
+    0 -- 1 [ ]
+    0 -- 4 [ ]
+    1 -- 5 [ ]
+    1 -- 3 [ ]
+    1 -- 4 [ ]
+}
+"#
+    );
+
+    // A and B can be walked between forever, unless one of them is closed off
+    let map = parse_input("start-A\nA-B\nB-end\nA-end");
+    let caves = Caves::new(&map);
+    let err = count_paths(&caves, &VisitPolicy::default()).unwrap_err();
+    assert_eq!(
+        err,
+        GraphError::AdjacentBigCaves("A".to_string(), "B".to_string())
+    );
+    assert_eq!(
+        err.to_string(),
+        r#"big caves "A" and "B" are adjacent, so there are infinitely many paths"#
+    );
+    assert!(paths(&caves, &VisitPolicy::default()).is_err());
+    let no_b = VisitPolicy {
+        forbidden: vec!["B"],
+        ..VisitPolicy::default()
+    };
+    assert_eq!(count_paths(&caves, &no_b), Ok(1));
+
+    // A big end next to a big cave is fine, since the path stops there
+    let map = parse_input("start-a\na-END\nEND-X");
+    let caves = Caves::new(&map);
+    let big_end = VisitPolicy {
+        end: "END",
+        ..VisitPolicy::default()
+    };
+    assert_eq!(count_paths(&caves, &big_end), Ok(1));
+    let found: Vec<_> = paths(&caves, &big_end).unwrap().collect();
+    assert_eq!(found, [vec!["start", "a", "END"]]);
 }
 
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
         let input_str = input1().unwrap();
-        let input = parse_input(&input_str);
-        part1(&input);
-        part2(&input);
+        let input = Caves::new(&parse_input(&input_str));
+        part1(&input).unwrap();
+        part2(&input).unwrap();
     })
 }
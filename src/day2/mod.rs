@@ -1,208 +1,243 @@
-use itertools::Itertools;
+use std::fmt;
 use std::fs;
-use std::ops::Add;
+use std::io;
 
 fn input1() -> std::io::Result<String> {
     fs::read_to_string("./src/day2/input.txt")
 }
 
-fn parse_line(line: &str) -> (&str, i32) {
-    if let Some((cmd, number)) = line.split_whitespace().collect_tuple() {
-        (
-            cmd,
-            number.parse().expect("Could not parse second value as i32"),
-        )
-    } else {
-        panic!("Expected exactly 2 elements")
-    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Forward(i32),
+    Down(i32),
+    Up(i32),
 }
 
-struct PositionOne {
-    horizontal: i32,
-    depth: i32,
+#[derive(Debug, PartialEq)]
+enum ParseErrorKind {
+    WrongArity(usize),
+    UnknownCommand(String),
+    BadValue(String),
 }
 
-impl Default for PositionOne {
-    fn default() -> Self {
-        PositionOne {
-            horizontal: 0,
-            depth: 0,
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    line: usize,
+    kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::WrongArity(n) => {
+                write!(f, "line {}: expected 2 elements, got {}", self.line, n)
+            }
+            ParseErrorKind::UnknownCommand(cmd) => {
+                write!(f, "line {}: unknown command {:?}", self.line, cmd)
+            }
+            ParseErrorKind::BadValue(v) => {
+                write!(f, "line {}: could not parse {:?} as i32", self.line, v)
+            }
         }
     }
 }
 
-impl PositionOne {
-    fn new() -> Self {
-        Default::default()
+impl std::error::Error for ParseError {}
+
+impl Command {
+    fn from_line(line: &str) -> Result<Self, ParseErrorKind> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (cmd, value) = match parts[..] {
+            [cmd, value] => (cmd, value),
+            _ => return Err(ParseErrorKind::WrongArity(parts.len())),
+        };
+        let v = value
+            .parse()
+            .map_err(|_| ParseErrorKind::BadValue(value.to_string()))?;
+        match cmd {
+            "forward" => Ok(Command::Forward(v)),
+            "down" => Ok(Command::Down(v)),
+            "up" => Ok(Command::Up(v)),
+            other => Err(ParseErrorKind::UnknownCommand(other.to_string())),
+        }
     }
 }
 
-struct PositionTwo {
-    horizontal: i32,
-    depth: i32,
-    aim: i32,
+fn parse_input(input: &str) -> Result<Vec<Command>, ParseError> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| Command::from_line(line).map_err(|kind| ParseError { line: i + 1, kind }))
+        .collect()
 }
 
-impl Default for PositionTwo {
-    fn default() -> Self {
-        PositionTwo {
-            horizontal: 0,
-            depth: 0,
-            aim: 0,
+// Interpretation of a command stream; implement this to run the same parsed
+// commands under different rules.
+trait Semantics {
+    fn apply(&mut self, cmd: Command);
+    fn horizontal(&self) -> i32;
+    fn depth(&self) -> i32;
+
+    fn aim(&self) -> i32 {
+        0
+    }
+
+    fn state(&self, step: usize) -> Step {
+        Step {
+            step,
+            horizontal: self.horizontal(),
+            depth: self.depth(),
+            aim: self.aim(),
         }
     }
-}
 
-impl PositionTwo {
-    fn new() -> Self {
-        Default::default()
+    fn run(mut self, commands: &[Command]) -> Self
+    where
+        Self: Sized,
+    {
+        for cmd in commands {
+            self.apply(*cmd);
+        }
+        self
     }
-}
 
-trait UpdatePosition {
-    fn update_position(&mut self, line: &str);
-    fn update_position_all(&mut self, plan: &str) {
-        for line in plan.lines() {
-            self.update_position(line);
+    // Like run, but keeps the state before the first and after every command
+    fn record(mut self, commands: &[Command]) -> (Self, Trajectory)
+    where
+        Self: Sized,
+    {
+        let mut steps = Vec::with_capacity(commands.len() + 1);
+        steps.push(self.state(0));
+        for (i, cmd) in commands.iter().enumerate() {
+            self.apply(*cmd);
+            steps.push(self.state(i + 1));
         }
+        (self, Trajectory { steps })
     }
+
+    fn product(&self) -> i32 {
+        self.horizontal() * self.depth()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Simple {
+    horizontal: i32,
+    depth: i32,
 }
 
-impl UpdatePosition for PositionOne {
-    fn update_position(&mut self, line: &str) {
-        let (cmd, number) = parse_line(line);
+impl Semantics for Simple {
+    fn apply(&mut self, cmd: Command) {
         match cmd {
-            "forward" => self.horizontal += number,
-            "down" => self.depth += number,
-            "up" => self.depth -= number,
-            _ => panic!("Unknown command"),
+            Command::Forward(v) => self.horizontal += v,
+            Command::Down(v) => self.depth += v,
+            Command::Up(v) => self.depth -= v,
         }
     }
+
+    fn horizontal(&self) -> i32 {
+        self.horizontal
+    }
+
+    fn depth(&self) -> i32 {
+        self.depth
+    }
+}
+
+#[derive(Debug, Default)]
+struct Aimed {
+    horizontal: i32,
+    depth: i32,
+    aim: i32,
 }
 
-impl UpdatePosition for PositionTwo {
-    fn update_position(&mut self, line: &str) {
-        let (cmd, number) = parse_line(line);
+impl Semantics for Aimed {
+    fn apply(&mut self, cmd: Command) {
         match cmd {
-            "forward" => {
-                self.horizontal += number;
-                self.depth += number * self.aim;
+            Command::Forward(v) => {
+                self.horizontal += v;
+                self.depth += v * self.aim;
             }
-            "down" => self.aim += number,
-            "up" => self.aim -= number,
-            _ => panic!("Unknown command"),
+            Command::Down(v) => self.aim += v,
+            Command::Up(v) => self.aim -= v,
         }
     }
-}
 
-fn part1(input: &str) -> i32 {
-    let mut pos = PositionOne::new();
-    pos.update_position_all(input);
-    pos.horizontal * pos.depth
-}
+    fn horizontal(&self) -> i32 {
+        self.horizontal
+    }
 
-fn part2(input: &str) -> i32 {
-    let mut pos = PositionTwo::new();
-    pos.update_position_all(input);
-    pos.horizontal * pos.depth
-}
+    fn depth(&self) -> i32 {
+        self.depth
+    }
 
-pub fn main() -> std::io::Result<()> {
-    let input = input1()?;
-    println!("{}", part1(&input));
-    println!("{}", part2(&input));
-    Ok(())
+    fn aim(&self) -> i32 {
+        self.aim
+    }
 }
 
-enum Direction {
-    Forward(i32),
-    Down(i32),
-    Up(i32),
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Step {
+    step: usize,
+    horizontal: i32,
+    depth: i32,
+    aim: i32,
 }
 
-impl Direction {
-    fn from_line(line: &str) -> Self {
-        let blah: Vec<&str> = line.split_whitespace().collect();
-        let v: i32 = blah[1].parse().expect("i32");
-        match blah[0] {
-            "forward" => Direction::Forward(v),
-            "down" => Direction::Down(v),
-            "up" => Direction::Up(v),
-            d => panic!("bad direction {}", d),
-        }
-    }
+#[derive(Debug, PartialEq)]
+struct Summary {
+    max_depth: i32,
+    max_depth_step: usize,
+    final_state: Step,
 }
 
-struct Pos {
-    f: i32,
-    d: i32,
+#[derive(Debug, Default)]
+struct Trajectory {
+    steps: Vec<Step>,
 }
 
-impl Add<Direction> for Pos {
-    type Output = Pos;
-    fn add(self, dir: Direction) -> Pos {
-        match dir {
-            Direction::Forward(i) => Pos {
-                d: self.d,
-                f: self.f + i,
-            },
-            Direction::Down(i) => Pos {
-                d: self.d + i,
-                f: self.f,
-            },
-            Direction::Up(i) => Pos {
-                d: self.d - i,
-                f: self.f,
-            },
-        }
+impl Trajectory {
+    fn summary(&self) -> Option<Summary> {
+        // min_by_key on the negated depth keeps the first step reaching the max
+        let deepest = self.steps.iter().min_by_key(|s| -s.depth)?;
+        Some(Summary {
+            max_depth: deepest.depth,
+            max_depth_step: deepest.step,
+            final_state: *self.steps.last()?,
+        })
     }
-}
 
-struct Pos2 {
-    f: i32,
-    d: i32,
-    aim: i32,
-}
-
-impl Add<Direction> for Pos2 {
-    type Output = Pos2;
-    fn add(self, dir: Direction) -> Pos2 {
-        match dir {
-            Direction::Forward(i) => Pos2 {
-                d: self.d + self.aim * i,
-                f: self.f + i,
-                aim: self.aim,
-            },
-            Direction::Down(i) => Pos2 {
-                d: self.d,
-                f: self.f,
-                aim: self.aim + i,
-            },
-            Direction::Up(i) => Pos2 {
-                d: self.d,
-                f: self.f,
-                aim: self.aim - i,
-            },
+    fn write_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(["step", "horizontal", "depth", "aim"])?;
+        for s in &self.steps {
+            wtr.write_record(&[
+                s.step.to_string(),
+                s.horizontal.to_string(),
+                s.depth.to_string(),
+                s.aim.to_string(),
+            ])?;
         }
+        wtr.flush()?;
+        Ok(())
     }
 }
 
-#[allow(dead_code)]
-fn part1_jeff(input: &str) -> i32 {
-    let pos = input
-        .lines()
-        .map(|line| Direction::from_line(line))
-        .fold(Pos { d: 0, f: 0 }, |acc, x| acc + x);
-    pos.d * pos.f
+fn part1(commands: &[Command]) -> i32 {
+    Simple::default().run(commands).product()
 }
 
-#[allow(dead_code)]
-fn part2_jeff(input: &str) -> i32 {
-    let pos = input
-        .lines()
-        .map(|line| Direction::from_line(line))
-        .fold(Pos2 { d: 0, f: 0, aim: 0 }, |acc, x| acc + x);
-    pos.d * pos.f
+fn part2(commands: &[Command]) -> i32 {
+    Aimed::default().run(commands).product()
+}
+
+pub fn main() -> std::io::Result<()> {
+    let commands = parse_input(&input1()?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    println!("{}", part1(&commands));
+    println!("{}", part2(&commands));
+    Ok(())
 }
 
 #[test]
@@ -213,17 +248,112 @@ fn example() {
     up 3
     down 8
     forward 2";
-    assert_eq!(part1(&input), 150);
-    assert_eq!(part1_jeff(&input), 150);
-    assert_eq!(part2(&input), 900);
-    assert_eq!(part2_jeff(&input), 900);
+    let commands = parse_input(input).unwrap();
+    assert_eq!(part1(&commands), 150);
+    assert_eq!(part2(&commands), 900);
 }
 
 #[test]
 fn task() {
-    let input = input1().unwrap();
-    assert_eq!(part1(&input), 1427868);
-    assert_eq!(part1_jeff(&input), 1427868);
-    assert_eq!(part2(&input), 1568138742);
-    assert_eq!(part2_jeff(&input), 1568138742);
+    let commands = parse_input(&input1().unwrap()).unwrap();
+    assert_eq!(part1(&commands), 1427868);
+    assert_eq!(part2(&commands), 1568138742);
+}
+
+#[test]
+fn custom_semantics() {
+    // Up and down swapped, forward counts double
+    #[derive(Default)]
+    struct Inverted {
+        horizontal: i32,
+        depth: i32,
+    }
+
+    impl Semantics for Inverted {
+        fn apply(&mut self, cmd: Command) {
+            match cmd {
+                Command::Forward(v) => self.horizontal += 2 * v,
+                Command::Down(v) => self.depth -= v,
+                Command::Up(v) => self.depth += v,
+            }
+        }
+
+        fn horizontal(&self) -> i32 {
+            self.horizontal
+        }
+
+        fn depth(&self) -> i32 {
+            self.depth
+        }
+    }
+
+    let commands = parse_input("forward 5\ndown 5\nforward 8\nup 3\ndown 8\nforward 2").unwrap();
+    let pos = Inverted::default().run(&commands);
+    assert_eq!((pos.horizontal(), pos.depth()), (30, -10));
+    assert_eq!(part1(&commands), 150);
+}
+
+#[test]
+fn trajectory() {
+    let commands = parse_input("forward 5\ndown 5\nforward 8\nup 3\ndown 8\nforward 2").unwrap();
+    let (pos, trajectory) = Aimed::default().record(&commands);
+    assert_eq!(trajectory.steps.len(), commands.len() + 1);
+    assert_eq!(
+        trajectory.summary(),
+        Some(Summary {
+            max_depth: 60,
+            max_depth_step: 6,
+            final_state: Step {
+                step: 6,
+                horizontal: 15,
+                depth: 60,
+                aim: 10
+            },
+        })
+    );
+    assert_eq!(pos.product(), 900);
+
+    let mut out = Vec::new();
+    trajectory.write_csv(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "step,horizontal,depth,aim
+0,0,0,0
+1,5,0,0
+2,5,0,5
+3,13,40,5
+4,13,40,2
+5,13,40,10
+6,15,60,10
+"
+    );
+
+    let (_, trajectory) = Simple::default().record(&commands);
+    let summary = trajectory.summary().unwrap();
+    assert_eq!((summary.max_depth, summary.max_depth_step), (10, 5));
+    assert!(trajectory.steps.iter().all(|s| s.aim == 0));
+
+    assert_eq!(Trajectory::default().summary(), None);
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(
+        parse_input("forward 5\nsideways 3"),
+        Err(ParseError {
+            line: 2,
+            kind: ParseErrorKind::UnknownCommand("sideways".to_string())
+        })
+    );
+    assert_eq!(
+        parse_input("forward 5\n\ndown x"),
+        Err(ParseError {
+            line: 3,
+            kind: ParseErrorKind::BadValue("x".to_string())
+        })
+    );
+    assert_eq!(
+        parse_input("up 1 2").unwrap_err().to_string(),
+        "line 1: expected 2 elements, got 3"
+    );
 }
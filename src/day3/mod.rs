@@ -1,4 +1,7 @@
 extern crate test;
+use bit_vec::BitVec;
+use num::BigUint;
+use std::fmt;
 use std::fs;
 
 #[cfg(test)]
@@ -8,67 +11,190 @@ fn input1() -> std::io::Result<String> {
     fs::read_to_string("./src/day3/input.txt")
 }
 
-fn to_u32(slice: &[bool]) -> u32 {
-    slice.iter().fold(0, |acc, &b| acc * 2 + b as u32)
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    Empty,
+    InconsistentWidth {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    NotBinary {
+        line: usize,
+        column: usize,
+        found: char,
+    },
 }
 
-fn parse(input: &str) -> Vec<u16> {
-    input
-        .lines()
-        .map(|v| u16::from_str_radix(v.trim(), 2))
-        .collect::<Result<_, _>>()
-        .unwrap()
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "report contains no lines"),
+            Self::InconsistentWidth {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} bits, found {}",
+                line, expected, found
+            ),
+            Self::NotBinary {
+                line,
+                column,
+                found,
+            } => write!(
+                f,
+                "line {}, column {}: {:?} is not 0 or 1",
+                line, column, found
+            ),
+        }
+    }
 }
 
-fn nth(v: &u16, bits: usize, n: usize) -> u16 {
-    (v & (1 << bits - 1) >> n) >> (bits - 1 - n)
+impl std::error::Error for ParseError {}
+
+// Rows are stored most significant bit first, so bit n of a row is column n
+#[derive(Debug, PartialEq)]
+struct Report {
+    width: usize,
+    rows: Vec<BitVec>,
 }
 
-fn sum_nth_bit(bits_slice: &[u16], n: usize, bits: usize) -> usize {
-    bits_slice
-        .iter()
-        .fold(0, |acc, v| acc + nth(v, bits, n) as usize)
+fn to_biguint<I: IntoIterator<Item = bool>>(bits: I) -> BigUint {
+    bits.into_iter()
+        .fold(BigUint::from(0u32), |acc, b| acc * 2u32 + b as u32)
 }
 
-fn part1(input: &str) -> u32 {
-    let bits = input.lines().next().unwrap().len();
-    let v = parse(input);
-    let half_n_elements = v.len() / 2;
-    let most_mask: Vec<_> = (0..bits)
-        .map(|i| sum_nth_bit(&v, i, bits) > half_n_elements)
-        .collect();
-    let least_mask: Vec<_> = most_mask.iter().map(|v| !v).collect();
-    to_u32(&most_mask) * to_u32(&least_mask)
-}
-
-fn retain_matches(bit_vec: &[u16], bits: usize, flip: bool) -> u32 {
-    let mut tmp_bit_vec = bit_vec.to_vec();
-    for i in 0..bits {
-        let half_n_elements = tmp_bit_vec.len() as f32 / 2.0;
-        let sum_of_nth = sum_nth_bit(&tmp_bit_vec, i, bits);
-        let desired_bit = (sum_of_nth as f32 >= half_n_elements) ^ flip;
-        tmp_bit_vec.retain(|&v| nth(&v, bits, i) == desired_bit as u16);
-        if tmp_bit_vec.len() == 1 {
-            break;
+fn parse_line(line: &str, line_no: usize) -> Result<BitVec, ParseError> {
+    line.chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            found => Err(ParseError::NotBinary {
+                line: line_no,
+                column: i + 1,
+                found,
+            }),
+        })
+        .collect()
+}
+
+fn parse(input: &str) -> Result<Report, ParseError> {
+    let mut width = None;
+    let mut rows = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
         }
+        let row = parse_line(line, i + 1)?;
+        let expected = *width.get_or_insert(row.len());
+        if row.len() != expected {
+            return Err(ParseError::InconsistentWidth {
+                line: i + 1,
+                expected,
+                found: row.len(),
+            });
+        }
+        rows.push(row);
     }
-    tmp_bit_vec[0] as u32
+    Ok(Report {
+        width: width.ok_or(ParseError::Empty)?,
+        rows,
+    })
 }
 
-fn part2(input: &str) -> u32 {
-    let bits = input.lines().next().unwrap().len();
-    let bit_vec = parse(input);
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Criterion {
+    MostCommon,
+    LeastCommon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TiePolicy {
+    PreferOne,
+    PreferZero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ColumnStats {
+    ones: usize,
+    zeros: usize,
+}
 
-    let oxy = retain_matches(&bit_vec, bits, false);
-    let co2 = retain_matches(&bit_vec, bits, true);
+impl ColumnStats {
+    fn select(&self, criterion: Criterion, tie: TiePolicy) -> bool {
+        if self.ones == self.zeros {
+            return tie == TiePolicy::PreferOne;
+        }
+        match criterion {
+            Criterion::MostCommon => self.ones > self.zeros,
+            Criterion::LeastCommon => self.ones < self.zeros,
+        }
+    }
+}
 
+fn nth_column_stats(rows: &[BitVec], n: usize) -> ColumnStats {
+    let ones = rows.iter().filter(|row| row[n]).count();
+    ColumnStats {
+        ones,
+        zeros: rows.len() - ones,
+    }
+}
+
+fn column_stats(report: &Report) -> Vec<ColumnStats> {
+    (0..report.width)
+        .map(|i| nth_column_stats(&report.rows, i))
+        .collect()
+}
+
+// Per-column selection over the whole report, as used for gamma/epsilon
+fn rate(report: &Report, criterion: Criterion, tie: TiePolicy) -> BigUint {
+    to_biguint(
+        column_stats(report)
+            .iter()
+            .map(|stats| stats.select(criterion, tie)),
+    )
+}
+
+// Iterative filtering as used for the oxygen/CO2 ratings. A column where all
+// remaining rows agree cannot discriminate between them and is skipped, so the
+// selection never filters out every row.
+fn rating(report: &Report, criterion: Criterion, tie: TiePolicy) -> BigUint {
+    let mut rows = report.rows.clone();
+    for i in 0..report.width {
+        if rows.len() == 1 {
+            break;
+        }
+        let stats = nth_column_stats(&rows, i);
+        if stats.ones == 0 || stats.zeros == 0 {
+            continue;
+        }
+        let desired_bit = stats.select(criterion, tie);
+        rows.retain(|row| row[i] == desired_bit);
+    }
+    to_biguint(&rows[0])
+}
+
+fn part1(report: &Report) -> BigUint {
+    let gamma = rate(report, Criterion::MostCommon, TiePolicy::PreferZero);
+    let epsilon = rate(report, Criterion::LeastCommon, TiePolicy::PreferOne);
+    gamma * epsilon
+}
+
+fn part2(report: &Report) -> BigUint {
+    let oxy = rating(report, Criterion::MostCommon, TiePolicy::PreferOne);
+    let co2 = rating(report, Criterion::LeastCommon, TiePolicy::PreferZero);
     oxy * co2
 }
 
 pub fn main() -> std::io::Result<()> {
-    let input = input1()?;
-    println!("{:?}", part1(&input));
-    println!("{:?}", part2(&input));
+    let report =
+        parse(&input1()?).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    println!("{:?}", part1(&report));
+    println!("{:?}", part2(&report));
     Ok(())
 }
 
@@ -86,22 +212,120 @@ fn example() {
     11001
     00010
     01010";
-    assert_eq!(part1(input), 198);
-    assert_eq!(part2(input), 230);
+    let report = parse(input).unwrap();
+    assert_eq!(part1(&report), BigUint::from(198u32));
+    assert_eq!(part2(&report), BigUint::from(230u32));
 }
 
 #[test]
 fn task() {
-    let input = input1().unwrap();
-    assert_eq!(part1(&input), 1458194);
-    assert_eq!(part2(&input), 2829354);
+    let report = parse(&input1().unwrap()).unwrap();
+    assert_eq!(part1(&report), BigUint::from(1458194u32));
+    assert_eq!(part2(&report), BigUint::from(2829354u32));
+}
+
+#[test]
+fn wide() {
+    // Example padded to 70 bits, which pushes the rates past u64
+    let input = "00100
+    11110
+    10110
+    10111
+    10101
+    01111
+    00111
+    11100
+    10000
+    11001
+    00010
+    01010";
+    let zeros = "0".repeat(65);
+    let padded: String = input
+        .lines()
+        .map(|l| format!("{}{}\n", zeros, l.trim()))
+        .collect();
+    let report = parse(&padded).unwrap();
+    let all_ones = (BigUint::from(1u32) << 70) - 1u32;
+    assert_eq!(report.width, 70);
+    assert_eq!(part1(&report), BigUint::from(22u32) * (all_ones - 22u32));
+    assert_eq!(part2(&report), BigUint::from(230u32));
+
+    let ones = "1".repeat(65);
+    let padded: String = input
+        .lines()
+        .map(|l| format!("{}{}\n", ones, l.trim()))
+        .collect();
+    let report = parse(&padded).unwrap();
+    let gamma = (BigUint::from(1u32) << 70) - 32u32 + 22u32;
+    let epsilon = BigUint::from(9u32);
+    assert_eq!(part1(&report), gamma * epsilon);
+    let high = (BigUint::from(1u32) << 70) - 32u32;
+    assert_eq!(part2(&report), (high.clone() + 23u32) * (high + 10u32));
+}
+
+#[test]
+fn ratings() {
+    let input = "00100
+    11110
+    10110
+    10111
+    10101
+    01111
+    00111
+    11100
+    10000
+    11001
+    00010
+    01010";
+    let report = parse(input).unwrap();
+    assert_eq!(
+        column_stats(&report)[..2],
+        [
+            ColumnStats { ones: 7, zeros: 5 },
+            ColumnStats { ones: 5, zeros: 7 }
+        ]
+    );
+
+    let stats = ColumnStats { ones: 3, zeros: 3 };
+    assert!(stats.select(Criterion::LeastCommon, TiePolicy::PreferOne));
+    assert!(!stats.select(Criterion::MostCommon, TiePolicy::PreferZero));
+
+    let oxy = |tie| rating(&report, Criterion::MostCommon, tie);
+    assert_eq!(oxy(TiePolicy::PreferOne), BigUint::from(23u32));
+    // Ties occur at the 4th bit of 1011x, so the other policy picks 10110
+    assert_eq!(oxy(TiePolicy::PreferZero), BigUint::from(22u32));
+    assert_eq!(
+        rating(&report, Criterion::LeastCommon, TiePolicy::PreferZero),
+        BigUint::from(10u32)
+    );
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(parse(""), Err(ParseError::Empty));
+    assert_eq!(
+        parse("0101\n011\n"),
+        Err(ParseError::InconsistentWidth {
+            line: 2,
+            expected: 4,
+            found: 3
+        })
+    );
+    assert_eq!(
+        parse("0101\n0121\n"),
+        Err(ParseError::NotBinary {
+            line: 2,
+            column: 3,
+            found: '2'
+        })
+    );
 }
 
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
-        let input = input1().unwrap();
-        part1(&input);
-        part2(&input);
+        let report = parse(&input1().unwrap()).unwrap();
+        part1(&report);
+        part2(&report);
     })
 }
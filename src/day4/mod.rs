@@ -1,7 +1,7 @@
 extern crate test;
-use itertools::Itertools;
-use ndarray::{Array2, Axis};
+use ndarray::Array2;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 
 #[cfg(test)]
@@ -13,149 +13,328 @@ fn input1() -> std::io::Result<String> {
     fs::read_to_string("./src/day4/input.txt")
 }
 
-fn read_input(input: &str) -> (Vec<u32>, Vec<Array2<u32>>) {
-    let (numbers, boards_str) = input.split_once("\n\n").unwrap();
-    let numbers = numbers
-        .split(",")
-        .map(|v| v.parse())
-        .collect::<Result<_, _>>()
-        .unwrap();
-
-    let board_size = input
-        .lines()
-        .skip(2)
-        .next()
-        .unwrap()
-        .split_whitespace()
-        .count();
-
-    let mut boards: Vec<Array2<u32>> = Vec::new();
-    let mut data = Vec::new();
-    for line in boards_str.lines() {
-        if line.trim() == "" {
-            let data_len = data.len();
-            let n_rows = data_len / board_size;
-            boards.push(Array2::from_shape_vec((n_rows, board_size), data.to_vec()).unwrap());
-            data.clear();
-        } else {
-            let mut row: Vec<u32> = line
-                .split_whitespace()
-                .map(|v| v.parse())
-                .collect::<Result<_, _>>()
-                .unwrap();
-            data.append(&mut row);
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    Empty,
+    BadNumber {
+        line: usize,
+        value: String,
+    },
+    RaggedBoard {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "input contains no numbers"),
+            Self::BadNumber { line, value } => {
+                write!(f, "line {}: could not parse {:?} as u32", line, value)
+            }
+            Self::RaggedBoard {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} columns like the rest of the board, found {}",
+                line, expected, found
+            ),
         }
     }
-    let data_len = data.len();
-    let n_rows = data_len / board_size;
-    boards.push(Array2::from_shape_vec((n_rows, board_size), data.to_vec()).unwrap());
+}
+
+impl std::error::Error for ParseError {}
 
-    (numbers, boards)
+fn parse_numbers<'a, I: Iterator<Item = &'a str>>(
+    values: I,
+    line: usize,
+) -> Result<Vec<u32>, ParseError> {
+    values
+        .map(|v| {
+            v.trim().parse().map_err(|_| ParseError::BadNumber {
+                line,
+                value: v.to_string(),
+            })
+        })
+        .collect()
 }
 
-fn build_reverse_index(boards: &Vec<Board>) -> Vec<HashMap<u32, Vec<(usize, usize)>>> {
-    let mut reverse_index = Vec::new();
+fn push_board(boards: &mut Vec<Board>, rows: &mut Vec<Vec<u32>>) {
+    if let Some(first) = rows.first() {
+        let shape = (rows.len(), first.len());
+        let data = rows.drain(..).flatten().collect();
+        boards.push(Array2::from_shape_vec(shape, data).unwrap());
+    }
+}
 
-    for b in boards {
-        reverse_index.push(HashMap::new());
-        for ((x, y), elem) in b.indexed_iter() {
-            reverse_index
-                .last_mut()
-                .unwrap()
-                .entry(elem.clone())
-                .or_insert(Vec::new())
-                .push((x, y));
+// Boards are separated by blank lines and may be any rectangular shape, but
+// every row of a board must have the same number of columns
+fn read_input(input: &str) -> Result<(Vec<u32>, Vec<Board>), ParseError> {
+    let mut lines = input.lines().enumerate();
+    let (_, first) = lines.next().ok_or(ParseError::Empty)?;
+    let numbers = parse_numbers(first.trim().split(','), 1)?;
+
+    let mut boards = Vec::new();
+    let mut rows: Vec<Vec<u32>> = Vec::new();
+    for (i, line) in lines {
+        if line.trim().is_empty() {
+            push_board(&mut boards, &mut rows);
+            continue;
+        }
+        let row = parse_numbers(line.split_whitespace(), i + 1)?;
+        if let Some(expected) = rows.first().map(|r| r.len()) {
+            if row.len() != expected {
+                return Err(ParseError::RaggedBoard {
+                    line: i + 1,
+                    expected,
+                    found: row.len(),
+                });
+            }
         }
+        rows.push(row);
     }
-    reverse_index
+    push_board(&mut boards, &mut rows);
+
+    Ok((numbers, boards))
 }
 
-fn calc_score(
-    winner_num: u32,
-    winner_idx: usize,
-    boards: &Vec<Board>,
-    marked_boards: Vec<Board>,
-) -> u32 {
-    (&boards[winner_idx] * &marked_boards[winner_idx]).sum() * winner_num
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WinPattern {
+    Rows,
+    Columns,
+    // Both main diagonals, only on square boards
+    Diagonals,
+    FourCorners,
+    FullCard,
 }
 
-fn mark(
-    i: usize,
-    num: &u32,
-    marked_boards: &mut Vec<Board>,
-    reverse_index: &Vec<HashMap<u32, Vec<(usize, usize)>>>,
-) {
-    if let Some(idx_vec) = reverse_index[i].get(num) {
-        for (x, y) in idx_vec {
-            let board = marked_boards.get_mut(i).unwrap();
-            board[[*x, *y]] = 0;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line {
+    Row(usize),
+    Column(usize),
+    Diagonal,
+    AntiDiagonal,
+    FourCorners,
+    FullCard,
+}
+
+// Every line the patterns produce for a board of the given shape, in pattern
+// order, with the cells that must all be marked for it to win
+fn lines(
+    patterns: &[WinPattern],
+    (n_rows, n_cols): (usize, usize),
+) -> Vec<(Line, Vec<(usize, usize)>)> {
+    let mut out = Vec::new();
+    for pattern in patterns {
+        match pattern {
+            WinPattern::Rows => out
+                .extend((0..n_rows).map(|r| (Line::Row(r), (0..n_cols).map(|c| (r, c)).collect()))),
+            WinPattern::Columns => out.extend(
+                (0..n_cols).map(|c| (Line::Column(c), (0..n_rows).map(|r| (r, c)).collect())),
+            ),
+            WinPattern::Diagonals if n_rows == n_cols => {
+                out.push((Line::Diagonal, (0..n_rows).map(|i| (i, i)).collect()));
+                out.push((
+                    Line::AntiDiagonal,
+                    (0..n_rows).map(|i| (i, n_cols - 1 - i)).collect(),
+                ));
+            }
+            WinPattern::Diagonals => (),
+            WinPattern::FourCorners => out.push((
+                Line::FourCorners,
+                vec![
+                    (0, 0),
+                    (0, n_cols - 1),
+                    (n_rows - 1, 0),
+                    (n_rows - 1, n_cols - 1),
+                ],
+            )),
+            WinPattern::FullCard => out.push((
+                Line::FullCard,
+                (0..n_rows)
+                    .flat_map(|r| (0..n_cols).map(move |c| (r, c)))
+                    .collect(),
+            )),
         }
     }
+    out
 }
 
-fn is_win_axis(marked_board: &Board, axis: Axis) -> bool {
-    marked_board
-        .sum_axis(axis)
-        .iter()
-        .map(|v| *v == 0 as u32)
-        .any(|x| x)
+// Cells of a board as bits, row-major, in as many words as the board needs
+#[derive(Debug, Clone, PartialEq)]
+struct Mask(Vec<u64>);
+
+impl Mask {
+    fn empty(cells: usize) -> Self {
+        Mask(vec![0; cells.div_ceil(64)])
+    }
+
+    fn set(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn get(&self, i: usize) -> bool {
+        self.0[i / 64] & 1 << (i % 64) != 0
+    }
+
+    fn covers(&self, other: &Mask) -> bool {
+        self.0.iter().zip(&other.0).all(|(a, b)| a & b == *b)
+    }
 }
 
-fn is_win(marked_board: &Board) -> bool {
-    is_win_axis(marked_board, Axis(0)) | is_win_axis(marked_board, Axis(1))
+struct BoardMasks {
+    values: Vec<u32>,
+    lines: Vec<(Line, Mask)>,
 }
 
-fn part1(numbers: &Vec<u32>, boards: &Vec<Board>) -> u32 {
-    let num_boards = boards.len();
-    let mut marked_boards: Vec<Board> = (0..boards.len())
-        .map(|i| Array2::ones(boards[i].dim()))
-        .collect_vec();
-    let reverse_index = build_reverse_index(&boards);
+impl BoardMasks {
+    fn new(board: &Board, patterns: &[WinPattern]) -> Self {
+        let n_cols = board.ncols();
+        let lines = lines(patterns, board.dim())
+            .into_iter()
+            .map(|(line, cells)| {
+                let mut mask = Mask::empty(board.len());
+                for (r, c) in cells {
+                    mask.set(r * n_cols + c);
+                }
+                (line, mask)
+            })
+            .collect();
+        BoardMasks {
+            values: board.iter().copied().collect(),
+            lines,
+        }
+    }
+
+    // Only lines touching the newly marked cells can have just been completed
+    fn winning_line(&self, marked: &Mask, new: &[usize]) -> Option<Line> {
+        self.lines
+            .iter()
+            .find(|(_, mask)| new.iter().any(|&c| mask.get(c)) && marked.covers(mask))
+            .map(|(line, _)| *line)
+    }
 
-    for num in numbers {
-        for i in 0..num_boards {
-            mark(i, &num, &mut marked_boards, &reverse_index);
-            if is_win(&marked_boards[i]) {
-                return calc_score(*num, i, boards, marked_boards);
+    fn unmarked_sum(&self, marked: &Mask) -> u32 {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !marked.get(*i))
+            .map(|(_, v)| v)
+            .sum()
+    }
+}
+
+// Draw number -> every board containing it, with the cells it covers there.
+// Boards are pushed in order so simultaneous winners come out by board index.
+fn build_draw_index(boards: &[Board]) -> HashMap<u32, Vec<(usize, Vec<usize>)>> {
+    let mut index: HashMap<u32, Vec<(usize, Vec<usize>)>> = HashMap::new();
+    for (i, board) in boards.iter().enumerate() {
+        for (cell, value) in board.iter().enumerate() {
+            let entries = index.entry(*value).or_default();
+            match entries.last_mut() {
+                Some((board, cells)) if *board == i => cells.push(cell),
+                _ => entries.push((i, vec![cell])),
             }
         }
     }
-    unreachable!()
+    index
 }
 
-fn part2(numbers: &Vec<u32>, boards: &Vec<Board>) -> u32 {
-    let num_boards = boards.len();
-    let mut marked_boards: Vec<Board> = (0..boards.len())
-        .map(|i| Array2::ones(boards[i].dim()))
-        .collect_vec();
+#[derive(Debug, Clone, PartialEq)]
+struct Win {
+    board: usize,
+    turn: usize,
+    number: u32,
+    line: Line,
+    score: u32,
+}
 
-    let mut rem_indices: Vec<usize> = (0..num_boards).collect();
-    let reverse_index = build_reverse_index(&boards);
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    // In the order the boards won, ties broken by board index
+    wins: Vec<Win>,
+    never_won: Vec<usize>,
+}
 
-    let mut winner_idx = 0;
+struct BingoGame<'a> {
+    numbers: &'a [u32],
+    boards: &'a [Board],
+    patterns: Vec<WinPattern>,
+}
 
-    for num in numbers {
-        if let [last_idx] = *rem_indices {
-            winner_idx = last_idx;
-        }
-        for i in &rem_indices {
-            mark(*i, &num, &mut marked_boards, &reverse_index);
+impl<'a> BingoGame<'a> {
+    fn new(numbers: &'a [u32], boards: &'a [Board]) -> Self {
+        BingoGame {
+            numbers,
+            boards,
+            patterns: vec![WinPattern::Rows, WinPattern::Columns],
         }
+    }
 
-        rem_indices = rem_indices
-            .into_iter()
-            .filter(|i| !is_win(&marked_boards[*i]))
+    fn with_patterns(mut self, patterns: &[WinPattern]) -> Self {
+        self.patterns = patterns.to_vec();
+        self
+    }
+
+    fn play(&self) -> Outcome {
+        let masks: Vec<_> = self
+            .boards
+            .iter()
+            .map(|b| BoardMasks::new(b, &self.patterns))
             .collect();
+        let index = build_draw_index(self.boards);
+        let mut marked: Vec<Mask> = self.boards.iter().map(|b| Mask::empty(b.len())).collect();
+        let mut won = vec![false; self.boards.len()];
+        let mut remaining = self.boards.len();
+        let mut outcome = Outcome::default();
 
-        if rem_indices.len() == 0 {
-            return calc_score(*num, winner_idx, boards, marked_boards);
+        for (turn, num) in self.numbers.iter().enumerate() {
+            if remaining == 0 {
+                break;
+            }
+            for (i, cells) in index.get(num).into_iter().flatten() {
+                let i = *i;
+                if won[i] {
+                    continue;
+                }
+                for &cell in cells {
+                    marked[i].set(cell);
+                }
+                if let Some(line) = masks[i].winning_line(&marked[i], cells) {
+                    won[i] = true;
+                    remaining -= 1;
+                    outcome.wins.push(Win {
+                        board: i,
+                        turn,
+                        number: *num,
+                        line,
+                        score: masks[i].unmarked_sum(&marked[i]) * num,
+                    });
+                }
+            }
         }
+        outcome.never_won = (0..self.boards.len()).filter(|&i| !won[i]).collect();
+        outcome
     }
-    unreachable!()
+}
+
+fn part1(numbers: &[u32], boards: &[Board]) -> u32 {
+    let outcome = BingoGame::new(numbers, boards).play();
+    outcome.wins.first().expect("No board won").score
+}
+
+fn part2(numbers: &[u32], boards: &[Board]) -> u32 {
+    let outcome = BingoGame::new(numbers, boards).play();
+    outcome.wins.last().expect("No board won").score
 }
 
 pub fn main() -> std::io::Result<()> {
-    let (numbers, boards) = read_input(&input1()?);
+    let (numbers, boards) = read_input(&input1()?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
     println!("{:?}", part1(&numbers, &boards));
     println!("{:?}", part2(&numbers, &boards));
     Ok(())
@@ -182,22 +361,186 @@ fn example() {
     18  8 23 26 20
     22 11 13  6  5
      2  0 12  3  7";
-    let (numbers, boards) = read_input(input);
+    let (numbers, boards) = read_input(input).unwrap();
     assert_eq!(part1(&numbers, &boards), 4512);
     assert_eq!(part2(&numbers, &boards), 1924);
+
+    let outcome = BingoGame::new(&numbers, &boards).play();
+    assert_eq!(
+        outcome.wins[0],
+        Win {
+            board: 2,
+            turn: 11,
+            number: 24,
+            line: Line::Row(0),
+            score: 4512
+        }
+    );
+    assert_eq!(
+        outcome.wins.iter().map(|w| w.board).collect::<Vec<_>>(),
+        vec![2, 0, 1]
+    );
+    assert_eq!(outcome.wins[2].line, Line::Column(2));
+    assert!(outcome.never_won.is_empty());
+
+    let outcome = BingoGame::new(&numbers[..13], &boards).play();
+    assert_eq!(outcome.wins.len(), 1);
+    assert_eq!(outcome.never_won, vec![0, 1]);
 }
 
 #[test]
 fn task() {
-    let (numbers, boards) = read_input(&input1().unwrap());
+    let (numbers, boards) = read_input(&input1().unwrap()).unwrap();
     assert_eq!(part1(&numbers, &boards), 16716);
     assert_eq!(part2(&numbers, &boards), 4880);
 }
 
+#[test]
+fn win_patterns() {
+    let input = "1,5,9,13,2,3,4,8,12,16,6,11
+
+    1 2 3 4
+    5 6 7 8
+    9 10 11 12
+    13 14 15 16";
+    let (numbers, boards) = read_input(input).unwrap();
+    let play = |patterns: &[WinPattern]| {
+        BingoGame::new(&numbers, &boards)
+            .with_patterns(patterns)
+            .play()
+            .wins
+            .first()
+            .map(|w| (w.turn, w.line))
+    };
+    assert_eq!(play(&[WinPattern::Rows]), Some((6, Line::Row(0))));
+    assert_eq!(play(&[WinPattern::Columns]), Some((3, Line::Column(0))));
+    assert_eq!(play(&[WinPattern::Diagonals]), Some((11, Line::Diagonal)));
+    assert_eq!(
+        play(&[WinPattern::FourCorners]),
+        Some((9, Line::FourCorners))
+    );
+    assert_eq!(play(&[WinPattern::FullCard]), None);
+
+    // 2x3 board: diagonals never apply, corners and columns still do
+    let input = "4,3,1,6,2,5
+
+    1 2 3
+    4 5 6";
+    let (numbers, boards) = read_input(input).unwrap();
+    assert_eq!(boards[0].dim(), (2, 3));
+    let play = |patterns: &[WinPattern]| {
+        BingoGame::new(&numbers, &boards)
+            .with_patterns(patterns)
+            .play()
+            .wins
+            .first()
+            .map(|w| (w.turn, w.line, w.score))
+    };
+    assert_eq!(play(&[WinPattern::Diagonals]), None);
+    assert_eq!(
+        play(&[WinPattern::FourCorners]),
+        Some((3, Line::FourCorners, 7 * 6))
+    );
+    assert_eq!(
+        play(&[WinPattern::Rows, WinPattern::Columns]),
+        Some((2, Line::Column(0), 13))
+    );
+    assert_eq!(play(&[WinPattern::FullCard]), Some((5, Line::FullCard, 0)));
+}
+
+#[test]
+fn random_boards() {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    let mut rng = StdRng::seed_from_u64(4);
+    let mut numbers: Vec<u32> = (0..100).collect();
+    let boards: Vec<Board> = (0..1000)
+        .map(|_| {
+            let cells = numbers.choose_multiple(&mut rng, 25).copied().collect();
+            Array2::from_shape_vec((5, 5), cells).unwrap()
+        })
+        .collect();
+    numbers.shuffle(&mut rng);
+    let numbers = &numbers[..60];
+
+    // A board wins on the turn its earliest complete row or column is done
+    let mut drawn_at = HashMap::new();
+    for (turn, num) in numbers.iter().enumerate() {
+        drawn_at.insert(*num, turn);
+    }
+    let expected_turn = |board: &Board| {
+        let done = |cells: Vec<&u32>| {
+            cells
+                .iter()
+                .map(|v| drawn_at.get(v).copied())
+                .collect::<Option<Vec<_>>>()
+                .map(|turns| turns.into_iter().max().unwrap())
+        };
+        let rows = board.rows().into_iter().map(|r| done(r.iter().collect()));
+        let cols = board
+            .columns()
+            .into_iter()
+            .map(|c| done(c.iter().collect()));
+        rows.chain(cols).flatten().min()
+    };
+
+    let outcome = BingoGame::new(numbers, &boards).play();
+    assert_eq!(outcome.wins.len() + outcome.never_won.len(), boards.len());
+    for win in &outcome.wins {
+        assert_eq!(expected_turn(&boards[win.board]), Some(win.turn));
+    }
+    for i in &outcome.never_won {
+        assert_eq!(expected_turn(&boards[*i]), None);
+    }
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(read_input(""), Err(ParseError::Empty));
+    assert_eq!(
+        read_input("1,x\n\n1 2\n3 4"),
+        Err(ParseError::BadNumber {
+            line: 1,
+            value: "x".to_string()
+        })
+    );
+    assert_eq!(
+        read_input("1,2\n\n1 2\n3 4 5"),
+        Err(ParseError::RaggedBoard {
+            line: 4,
+            expected: 2,
+            found: 3
+        })
+    );
+}
+
+#[test]
+fn large_board() {
+    // 12x12 needs more than one word of marks per board
+    let board = Array2::from_shape_vec((12, 12), (0..144).collect()).unwrap();
+    let boards = [board];
+    let numbers: Vec<u32> = (0..12).map(|r| r * 12 + 11).collect();
+    let outcome = BingoGame::new(&numbers, &boards).play();
+    let total: u32 = (0..144).sum();
+    let column: u32 = numbers.iter().sum();
+    assert_eq!(outcome.wins[0].turn, 11);
+    assert_eq!(outcome.wins[0].line, Line::Column(11));
+    assert_eq!(outcome.wins[0].score, (total - column) * 143);
+
+    let numbers: Vec<u32> = (0..144).rev().collect();
+    let outcome = BingoGame::new(&numbers, &boards)
+        .with_patterns(&[WinPattern::FullCard])
+        .play();
+    assert_eq!(outcome.wins[0].turn, 143);
+    assert_eq!(outcome.wins[0].score, 0);
+}
+
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
-        let (numbers, boards) = read_input(&input1().unwrap());
+        let (numbers, boards) = read_input(&input1().unwrap()).unwrap();
         part1(&numbers, &boards);
         part2(&numbers, &boards);
     })
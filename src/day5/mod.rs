@@ -1,11 +1,15 @@
 extern crate test;
-use ndarray::Array2;
+use num::integer::gcd;
+#[cfg(test)]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 
 #[cfg(test)]
 use test::Bencher;
 
-type Segment = [[usize; 2]; 2];
+type Point = (i64, i64);
+type Segment = [Point; 2];
 
 fn input1() -> std::io::Result<String> {
     fs::read_to_string("./src/day5/input.txt")
@@ -21,63 +25,298 @@ fn parse_input(input: &str) -> Vec<Segment> {
             .map(|v| v.parse())
             .collect::<Result<Vec<_>, _>>()
             .unwrap();
-        out.push([[nums[0], nums[2]], [nums[1], nums[3]]]);
+        out.push([(nums[0], nums[1]), (nums[2], nums[3])]);
     }
     out
 }
 
-fn step(seg: [usize; 2]) -> i32 {
-    if seg[0] < seg[1] {
-        1
-    } else if seg[0] > seg[1] {
-        -1
-    } else {
-        0
-    }
+// Exactly one axis stays fixed, so single points only count with diagonals
+fn is_axis_aligned([(x0, y0), (x1, y1)]: Segment) -> bool {
+    (x0 == x1) != (y0 == y1)
 }
 
-fn increment(segment: Segment, board: &mut Array2<u32>) {
-    let xs = step(segment[0]);
-    let ys = step(segment[1]);
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Raster {
+    // Every cell the line passes through, one per step along the major axis
+    Bresenham,
+    // Only the points with integer coordinates that lie exactly on the line
+    Lattice,
+}
 
-    let mut x = segment[0][0];
-    let mut y = segment[1][0];
+fn bresenham([(x0, y0), (x1, y1)]: Segment) -> Vec<Point> {
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (xs, ys) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let mut err = dx + dy;
+    let (mut x, mut y) = (x0, y0);
+    let mut out = Vec::with_capacity((dx.max(-dy) + 1) as usize);
     loop {
-        board[[x, y]] += 1;
-        if x == segment[0][1] && y == segment[1][1] {
+        out.push((x, y));
+        if (x, y) == (x1, y1) {
             break;
         }
-        x = (x as i32 + xs).try_into().unwrap();
-        y = (y as i32 + ys).try_into().unwrap();
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += xs;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += ys;
+        }
     }
+    out
 }
 
-fn parts(input: &Vec<Segment>, board_size: usize, consider_diag: bool) -> usize {
-    let mut board = Array2::<u32>::zeros((board_size, board_size));
+fn lattice([(x0, y0), (x1, y1)]: Segment) -> Vec<Point> {
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let n = gcd(dx, dy);
+    if n == 0 {
+        return vec![(x0, y0)];
+    }
+    (0..=n)
+        .map(|i| (x0 + i * dx / n, y0 + i * dy / n))
+        .collect()
+}
+
+fn rasterize(segment: Segment, raster: Raster) -> Vec<Point> {
+    match raster {
+        Raster::Bresenham => bresenham(segment),
+        Raster::Lattice => lattice(segment),
+    }
+}
+
+// Only visited cells are stored, so there is no board size to pick and
+// coordinates may be arbitrarily large or negative
+fn dangerous_points(input: &[Segment], consider_diag: bool, raster: Raster) -> HashSet<Point> {
+    let mut counts: HashMap<Point, u32> = HashMap::new();
     for segment in input {
-        if (segment[0][0] == segment[0][1]) != (segment[1][0] == segment[1][1]) {
-            increment(*segment, &mut board);
-        } else if consider_diag {
-            increment(*segment, &mut board);
+        if consider_diag || is_axis_aligned(*segment) {
+            for p in rasterize(*segment, raster) {
+                *counts.entry(p).or_insert(0) += 1;
+            }
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(_, v)| *v >= 2)
+        .map(|(p, _)| p)
+        .collect()
+}
+
+// Both rasterizers agree on horizontal, vertical and 45 degree lines
+fn parts(input: &[Segment], consider_diag: bool) -> usize {
+    dangerous_points(input, consider_diag, Raster::Lattice).len()
+}
+
+fn cross(a: Point, b: Point) -> i64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn dot(a: Point, b: Point) -> i64 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+// A segment as its lattice points start + t * dir for t in 0..=steps, with dir
+// primitive (or zero for a single point)
+struct LatticeSegment {
+    start: Point,
+    dir: Point,
+    steps: i64,
+}
+
+impl LatticeSegment {
+    fn new([(x0, y0), (x1, y1)]: Segment) -> Self {
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let steps = gcd(dx, dy);
+        let dir = if steps == 0 {
+            (0, 0)
         } else {
-            continue;
+            (dx / steps, dy / steps)
+        };
+        LatticeSegment {
+            start: (x0, y0),
+            dir,
+            steps,
+        }
+    }
+
+    fn at(&self, t: i64) -> Point {
+        (self.start.0 + t * self.dir.0, self.start.1 + t * self.dir.1)
+    }
+
+    fn contains(&self, p: Point) -> bool {
+        let w = (p.0 - self.start.0, p.1 - self.start.1);
+        if self.steps == 0 {
+            return w == (0, 0);
+        }
+        if cross(self.dir, w) != 0 {
+            return false;
+        }
+        let t = dot(w, self.dir) / dot(self.dir, self.dir);
+        (0..=self.steps).contains(&t) && self.at(t) == p
+    }
+
+    // Direction with a fixed sign and the offset identifying the line
+    fn line(&self) -> (Point, i64) {
+        let d = if self.dir.0 < 0 || (self.dir.0 == 0 && self.dir.1 < 0) {
+            (-self.dir.0, -self.dir.1)
+        } else {
+            self.dir
+        };
+        (d, cross(d, self.start))
+    }
+
+    // Range of dot(p, d) over the segment's points, for d from line()
+    fn span(&self) -> (i64, i64) {
+        let (d, _) = self.line();
+        let (a, b) = (dot(self.start, d), dot(self.at(self.steps), d));
+        (a.min(b), a.max(b))
+    }
+
+    fn x_range(&self) -> (i64, i64) {
+        let (a, b) = (self.start.0, self.at(self.steps).0);
+        (a.min(b), a.max(b))
+    }
+
+    fn y_range(&self) -> (i64, i64) {
+        let (a, b) = (self.start.1, self.at(self.steps).1);
+        (a.min(b), a.max(b))
+    }
+}
+
+enum Intersection {
+    None,
+    Point(Point),
+    // Shared stretch of a common line, as a span of that line
+    Overlap((Point, i64), (i64, i64)),
+}
+
+fn intersect(a: &LatticeSegment, b: &LatticeSegment) -> Intersection {
+    if a.steps == 0 || b.steps == 0 {
+        let (point, other) = if a.steps == 0 { (a, b) } else { (b, a) };
+        return match other.contains(point.start) {
+            true => Intersection::Point(point.start),
+            false => Intersection::None,
+        };
+    }
+    let w = (b.start.0 - a.start.0, b.start.1 - a.start.1);
+    let denom = cross(a.dir, b.dir);
+    if denom == 0 {
+        if cross(a.dir, w) != 0 {
+            return Intersection::None;
         }
+        let ((lo_a, hi_a), (lo_b, hi_b)) = (a.span(), b.span());
+        let (lo, hi) = (lo_a.max(lo_b), hi_a.min(hi_b));
+        return match lo <= hi {
+            true => Intersection::Overlap(a.line(), (lo, hi)),
+            false => Intersection::None,
+        };
+    }
+    let (t, s) = (cross(w, b.dir), cross(w, a.dir));
+    if t % denom != 0 || s % denom != 0 {
+        return Intersection::None;
+    }
+    let (t, s) = (t / denom, s / denom);
+    match (0..=a.steps).contains(&t) && (0..=b.steps).contains(&s) {
+        true => Intersection::Point(a.at(t)),
+        false => Intersection::None,
     }
-    board.iter().filter(|v| v >= &&2).count()
 }
 
-fn part1(input: &Vec<Segment>, board_size: usize) -> usize {
-    parts(input, board_size, false)
+fn merge_spans(spans: &mut Vec<(i64, i64)>) {
+    spans.sort_unstable();
+    let mut merged: Vec<(i64, i64)> = Vec::with_capacity(spans.len());
+    for &(lo, hi) in spans.iter() {
+        match merged.last_mut() {
+            Some(last) if lo <= last.1 => last.1 = last.1.max(hi),
+            _ => merged.push((lo, hi)),
+        }
+    }
+    *spans = merged;
 }
 
-fn part2(input: &Vec<Segment>, board_size: usize) -> usize {
-    parts(input, board_size, true)
+// Same count as dangerous_points with Raster::Lattice, but computed from
+// pairwise intersections: collinear overlaps are counted as merged spans and
+// never enumerated, so the cost depends on the number of segments and not on
+// the area they cover. Pairs are pruned with a sweep over x.
+fn analytic_overlaps(input: &[Segment], consider_diag: bool) -> usize {
+    let mut segments: Vec<LatticeSegment> = input
+        .iter()
+        .filter(|s| consider_diag || is_axis_aligned(**s))
+        .map(|s| LatticeSegment::new(*s))
+        .collect();
+    segments.sort_by_key(|s| s.x_range().0);
+
+    let mut points = HashSet::new();
+    let mut overlaps: HashMap<(Point, i64), Vec<(i64, i64)>> = HashMap::new();
+    for (i, a) in segments.iter().enumerate() {
+        let (_, a_max_x) = a.x_range();
+        let (a_min_y, a_max_y) = a.y_range();
+        for b in segments[i + 1..]
+            .iter()
+            .take_while(|b| b.x_range().0 <= a_max_x)
+        {
+            let (b_min_y, b_max_y) = b.y_range();
+            if b_min_y > a_max_y || a_min_y > b_max_y {
+                continue;
+            }
+            match intersect(a, b) {
+                Intersection::None => (),
+                Intersection::Point(p) => {
+                    points.insert(p);
+                }
+                Intersection::Overlap(line, span) => overlaps.entry(line).or_default().push(span),
+            }
+        }
+    }
+
+    let mut count = 0;
+    for (&(d, _), spans) in overlaps.iter_mut() {
+        merge_spans(spans);
+        let step = dot(d, d);
+        count += spans
+            .iter()
+            .map(|(lo, hi)| ((hi - lo) / step + 1) as usize)
+            .sum::<usize>();
+    }
+
+    // Every point shared by several overlaps on different lines is also a
+    // crossing of two of their segments, so correcting the crossing points
+    // for how many overlaps already counted them covers all double counting
+    let dirs: HashSet<Point> = overlaps.keys().map(|(d, _)| *d).collect();
+    let overlaps_containing = |p: Point| {
+        dirs.iter()
+            .filter(|&&d| match overlaps.get(&(d, cross(d, p))) {
+                Some(spans) => {
+                    let u = dot(p, d);
+                    let i = spans.partition_point(|(lo, _)| *lo <= u);
+                    i > 0 && spans[i - 1].1 >= u
+                }
+                None => false,
+            })
+            .count()
+    };
+    for p in points {
+        match overlaps_containing(p) {
+            0 => count += 1,
+            k => count -= k - 1,
+        }
+    }
+    count
+}
+
+fn part1(input: &[Segment]) -> usize {
+    parts(input, false)
+}
+
+fn part2(input: &[Segment]) -> usize {
+    parts(input, true)
 }
 
 pub fn main() -> std::io::Result<()> {
     let input = parse_input(&input1()?);
-    println!("{:?}", part1(&input, 1000));
-    println!("{:?}", part2(&input, 1000));
+    println!("{:?}", part1(&input));
+    println!("{:?}", part2(&input));
     Ok(())
 }
 
@@ -94,22 +333,124 @@ fn example() {
     0,0 -> 8,8
     5,5 -> 8,2";
     let input = parse_input(input);
-    assert_eq!(part1(&input, 10), 5);
-    assert_eq!(part2(&input, 10), 12);
+    assert_eq!(part1(&input), 5);
+    assert_eq!(part2(&input), 12);
+
+    let points = dangerous_points(&input, false, Raster::Lattice);
+    let expected = [(3, 4), (7, 4), (0, 9), (1, 9), (2, 9)];
+    assert_eq!(points, expected.into_iter().collect());
+}
+
+#[test]
+fn unbounded() {
+    // The example shifted far into negative x coordinates
+    let input = parse_input(
+        "-1000000,9 -> -999995,9
+    -999992,0 -> -1000000,8
+    -999991,4 -> -999997,4
+    -999998,2 -> -999998,1
+    -999993,0 -> -999993,4
+    -999994,4 -> -999998,0
+    -1000000,9 -> -999998,9
+    -999997,4 -> -999999,4
+    -1000000,0 -> -999992,8
+    -999995,5 -> -999992,2",
+    );
+    assert_eq!(part1(&input), 5);
+    assert_eq!(part2(&input), 12);
+    assert!(dangerous_points(&input, true, Raster::Lattice).contains(&(-999998, 2)));
 }
 
 #[test]
 fn task() {
     let input = parse_input(&input1().unwrap());
-    assert_eq!(part1(&input, 1000), 7142);
-    assert_eq!(part2(&input, 1000), 20012);
+    assert_eq!(part1(&input), 7142);
+    assert_eq!(part2(&input), 20012);
+}
+
+#[test]
+fn rasterizers() {
+    let input = parse_input(&input1().unwrap());
+    for raster in [Raster::Bresenham, Raster::Lattice] {
+        assert_eq!(dangerous_points(&input, false, raster).len(), 7142);
+        assert_eq!(dangerous_points(&input, true, raster).len(), 20012);
+    }
+
+    assert_eq!(
+        rasterize([(0, 0), (4, 2)], Raster::Bresenham),
+        vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]
+    );
+    assert_eq!(
+        rasterize([(0, 0), (4, 2)], Raster::Lattice),
+        vec![(0, 0), (2, 1), (4, 2)]
+    );
+    assert_eq!(
+        rasterize([(3, -1), (0, 1)], Raster::Lattice),
+        vec![(3, -1), (0, 1)]
+    );
+    assert_eq!(rasterize([(5, 5), (5, 5)], Raster::Bresenham), vec![(5, 5)]);
+    assert_eq!(rasterize([(5, 5), (5, 5)], Raster::Lattice), vec![(5, 5)]);
+
+    // Shallow lines crossing between lattice points only overlap when rasterized
+    let input = parse_input("0,0 -> 4,2\n0,1 -> 4,1");
+    assert_eq!(
+        dangerous_points(&input, true, Raster::Bresenham),
+        [(1, 1), (2, 1)].into_iter().collect()
+    );
+    assert_eq!(
+        dangerous_points(&input, true, Raster::Lattice),
+        [(2, 1)].into_iter().collect()
+    );
+}
+
+#[test]
+fn analytic() {
+    let input = parse_input(&input1().unwrap());
+    assert_eq!(analytic_overlaps(&input, false), 7142);
+    assert_eq!(analytic_overlaps(&input, true), 20012);
+
+    let mut rng = StdRng::seed_from_u64(5);
+    for _ in 0..100 {
+        let input: Vec<Segment> = (0..40)
+            .map(|_| {
+                let mut p = || (rng.gen_range(-8..8), rng.gen_range(-8..8));
+                [p(), p()]
+            })
+            .collect();
+        for consider_diag in [false, true] {
+            assert_eq!(
+                analytic_overlaps(&input, consider_diag),
+                dangerous_points(&input, consider_diag, Raster::Lattice).len()
+            );
+        }
+    }
+
+    // Far too large to rasterize: three overlapping collinear segments, one
+    // crossing inside their overlap and one outside
+    let input = parse_input(
+        "0,0 -> 0,10000000
+        0,20000000 -> 0,5000000
+        0,9000000 -> 0,30000000
+        -5,6000000 -> 5,6000000
+        -3000000,-2999999 -> 3000000,3000001",
+    );
+    assert_eq!(analytic_overlaps(&input, true), 15000001 + 1);
+}
+
+#[test]
+fn point_segments() {
+    let input = parse_input("1,1 -> 1,1\n1,1 -> 1,1\n0,1 -> 2,1");
+    assert_eq!(part1(&input), 0);
+    assert_eq!(analytic_overlaps(&input, false), 0);
+    assert_eq!(part2(&input), 1);
+    assert_eq!(analytic_overlaps(&input, true), 1);
 }
 
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
         let input = parse_input(&input1().unwrap());
-        part1(&input, 1000);
-        part2(&input, 1000);
+        part1(&input);
+        part2(&input);
     })
 }
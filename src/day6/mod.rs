@@ -1,4 +1,6 @@
 extern crate test;
+use num::{BigUint, One, Zero};
+use std::fmt;
 use std::fs;
 
 #[cfg(test)]
@@ -16,52 +18,306 @@ fn parse_input(input: &str) -> Vec<usize> {
         .unwrap()
 }
 
-fn parts(input: &Vec<usize>, days: u32) -> usize {
-    let mut counts = [0; 9];
-    for fish in input {
-        counts[*fish] += 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Lifecycle {
+    // Days between spawns for an adult
+    spawn_interval: usize,
+    // Extra days a newborn waits before its first cycle
+    newborn_delay: usize,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            spawn_interval: 7,
+            newborn_delay: 2,
+        }
+    }
+}
+
+impl Lifecycle {
+    fn slots(&self) -> usize {
+        self.spawn_interval + self.newborn_delay
+    }
+
+    fn reset_timer(&self) -> usize {
+        self.spawn_interval - 1
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ModelError {
+    ZeroSpawnInterval,
+    TimerOutOfRange {
+        index: usize,
+        timer: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroSpawnInterval => write!(f, "spawn interval must be at least 1"),
+            Self::TimerOutOfRange { index, timer, max } => write!(
+                f,
+                "fish {} has timer {}, but timers only go up to {}",
+                index, timer, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+fn timer_counts(lifecycle: &Lifecycle, timers: &[usize]) -> Result<Vec<BigUint>, ModelError> {
+    if lifecycle.spawn_interval == 0 {
+        return Err(ModelError::ZeroSpawnInterval);
+    }
+    let mut counts = vec![BigUint::zero(); lifecycle.slots()];
+    for (index, &timer) in timers.iter().enumerate() {
+        match counts.get_mut(timer) {
+            Some(c) => *c += 1u32,
+            None => {
+                return Err(ModelError::TimerOutOfRange {
+                    index,
+                    timer,
+                    max: lifecycle.slots() - 1,
+                })
+            }
+        }
+    }
+    Ok(counts)
+}
+
+#[derive(Debug, Clone)]
+struct Population {
+    lifecycle: Lifecycle,
+    counts: Vec<BigUint>,
+}
+
+impl Population {
+    fn new(lifecycle: Lifecycle, timers: &[usize]) -> Result<Self, ModelError> {
+        Ok(Population {
+            lifecycle,
+            counts: timer_counts(&lifecycle, timers)?,
+        })
+    }
+
+    // Parents land in the last slot with their newborns and are moved back
+    fn step(&mut self) {
+        self.counts.rotate_left(1);
+        let spawned = self.counts.last().unwrap().clone();
+        self.counts[self.lifecycle.reset_timer()] += spawned;
+    }
+
+    fn total(&self) -> BigUint {
+        self.counts.iter().sum()
+    }
+
+    // Population on day 0 through days inclusive
+    fn series(&self, days: usize) -> Vec<BigUint> {
+        let mut population = self.clone();
+        let mut out = Vec::with_capacity(days + 1);
+        out.push(population.total());
+        for _ in 0..days {
+            population.step();
+            out.push(population.total());
+        }
+        out
+    }
+}
+
+fn parts(population: &Population, days: usize) -> BigUint {
+    population.series(days).pop().unwrap()
+}
+
+type Matrix = Vec<Vec<BigUint>>;
+
+fn identity(n: usize) -> Matrix {
+    (0..n)
+        .map(|i| (0..n).map(|j| BigUint::from((i == j) as u32)).collect())
+        .collect()
+}
+
+fn mat_mul(a: &Matrix, b: &Matrix, modulus: Option<&BigUint>) -> Matrix {
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    let v = (0..n).fold(BigUint::zero(), |acc, k| acc + &a[i][k] * &b[k][j]);
+                    match modulus {
+                        Some(m) => v % m,
+                        None => v,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn mat_pow(mut base: Matrix, mut exp: u64, modulus: Option<&BigUint>) -> Matrix {
+    let mut out = identity(base.len());
+    while exp > 0 {
+        if exp & 1 == 1 {
+            out = mat_mul(&out, &base, modulus);
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = mat_mul(&base, &base, modulus);
+        }
     }
-    for _ in 0..days {
-        counts.rotate_left(1);
-        counts[6] += counts[8];
+    out
+}
+
+// counts_next = transition * counts: every timer moves down one slot, timer 0
+// resets to the lifecycle's reset timer and spawns a newborn in the last slot
+fn transition(lifecycle: &Lifecycle) -> Matrix {
+    let n = lifecycle.slots();
+    let mut m = vec![vec![BigUint::zero(); n]; n];
+    for i in 0..n - 1 {
+        m[i][i + 1] = BigUint::one();
     }
-    counts.iter().sum()
+    m[n - 1][0] += 1u32;
+    m[lifecycle.reset_timer()][0] += 1u32;
+    m
+}
+
+fn project_inner(population: &Population, days: u64, modulus: Option<&BigUint>) -> BigUint {
+    let m = mat_pow(transition(&population.lifecycle), days, modulus);
+    let total: BigUint = m
+        .iter()
+        .flat_map(|row| row.iter().zip(&population.counts).map(|(v, c)| v * c))
+        .sum();
+    match modulus {
+        Some(m) => total % m,
+        None => total,
+    }
+}
+
+// Exact population after any number of days, in O(log days) matrix products
+fn project(population: &Population, days: u64) -> BigUint {
+    project_inner(population, days, None)
 }
 
-fn part1(input: &Vec<usize>) -> usize {
-    parts(input, 80)
+fn project_mod(population: &Population, days: u64, modulus: &BigUint) -> BigUint {
+    project_inner(population, days, Some(modulus))
 }
 
-fn part2(input: &Vec<usize>) -> usize {
-    parts(input, 256)
+fn part1(population: &Population) -> BigUint {
+    parts(population, 80)
+}
+
+fn part2(population: &Population) -> BigUint {
+    parts(population, 256)
+}
+
+fn population(input: &str) -> Result<Population, ModelError> {
+    Population::new(Lifecycle::default(), &parse_input(input))
 }
 
 pub fn main() -> std::io::Result<()> {
-    let input = parse_input(&input1()?);
-    println!("{:?}", part1(&input));
-    println!("{:?}", part2(&input));
+    let input = population(&input1()?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    println!("{}", part1(&input));
+    println!("{}", part2(&input));
     Ok(())
 }
 
 #[test]
 fn example() {
-    let input = "3,4,3,1,2";
-    let input = parse_input(input);
-    assert_eq!(part1(&input), 5934);
-    assert_eq!(part2(&input), 26984457539);
+    let input = population("3,4,3,1,2").unwrap();
+    assert_eq!(part1(&input), BigUint::from(5934u32));
+    assert_eq!(part2(&input), BigUint::from(26984457539u64));
 }
 
 #[test]
 fn task() {
-    let input = parse_input(&input1().unwrap());
-    assert_eq!(part1(&input), 375482);
-    assert_eq!(part2(&input), 1689540415957);
+    let input = population(&input1().unwrap()).unwrap();
+    assert_eq!(part1(&input), BigUint::from(375482u32));
+    assert_eq!(part2(&input), BigUint::from(1689540415957u64));
+}
+
+#[test]
+fn projection() {
+    let input = population("3,4,3,1,2").unwrap();
+    assert_eq!(project(&input, 0), BigUint::from(5u32));
+    assert_eq!(project(&input, 80), BigUint::from(5934u32));
+    assert_eq!(project(&input, 256), BigUint::from(26984457539u64));
+    let task = population(&input1().unwrap()).unwrap();
+    assert_eq!(project(&task, 256), BigUint::from(1689540415957u64));
+
+    // Past the point where u64 overflows, checked against stepping day by day
+    let expected = input.series(1000).pop().unwrap();
+    assert!(expected > BigUint::from(u128::MAX));
+    assert_eq!(project(&input, 1000), expected);
+
+    let m = BigUint::from(1_000_000_007u32);
+    assert_eq!(project_mod(&input, 1000, &m), expected % &m);
+    assert_eq!(
+        project_mod(&input, 256, &m),
+        BigUint::from(26984457539u64 % 1_000_000_007)
+    );
+    let big = project(&input, 100_000);
+    assert_eq!(project_mod(&input, 100_000, &m), big % &m);
+    // Far beyond anything iterable, only feasible modulo m
+    assert!(project_mod(&input, 10u64.pow(18), &m) < m);
+}
+
+#[test]
+fn lifecycles() {
+    let input = parse_input("3,4,3,1,2");
+    let standard = Population::new(Lifecycle::default(), &input).unwrap();
+    assert_eq!(
+        standard.series(18),
+        [5u32, 5, 6, 7, 9, 10, 10, 10, 10, 11, 12, 15, 17, 19, 20, 20, 21, 22, 26]
+            .map(BigUint::from)
+    );
+
+    // Five day cycle with newborns starting right away: every fish doubles
+    // each time its timer wraps
+    let fast = Lifecycle {
+        spawn_interval: 5,
+        newborn_delay: 0,
+    };
+    let population = Population::new(fast, &[4]).unwrap();
+    assert_eq!(
+        population.series(15),
+        [1u32, 1, 1, 1, 1, 2, 2, 2, 2, 2, 4, 4, 4, 4, 4, 8].map(BigUint::from)
+    );
+    assert_eq!(project(&population, 15), BigUint::from(8u32));
+
+    let slow = Lifecycle {
+        spawn_interval: 5,
+        newborn_delay: 3,
+    };
+    let population = Population::new(slow, &[0, 7]).unwrap();
+    let series = population.series(200);
+    assert_eq!(project(&population, 200), series[200]);
+
+    assert_eq!(
+        Population::new(fast, &[2, 5]).unwrap_err(),
+        ModelError::TimerOutOfRange {
+            index: 1,
+            timer: 5,
+            max: 4
+        }
+    );
+    let invalid = Lifecycle {
+        spawn_interval: 0,
+        newborn_delay: 2,
+    };
+    assert_eq!(
+        Population::new(invalid, &[0]).unwrap_err(),
+        ModelError::ZeroSpawnInterval
+    );
 }
 
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
-        let input = parse_input(&input1().unwrap());
+        let input = population(&input1().unwrap()).unwrap();
         part1(&input);
         part2(&input);
     })
@@ -28,24 +28,191 @@ fn cost_p2(l: i64, r: i64) -> i64 {
     triangle((l - r).abs())
 }
 
-fn part1(input: &Vec<i64>) -> i64 {
-    let dest = statistical::median(&input);
-    input.iter().map(|v| cost_p1(*v, dest)).sum()
+fn cost_quadratic(l: i64, r: i64) -> i64 {
+    (l - r) * (l - r)
 }
 
-fn part2(input: &Vec<i64>) -> i64 {
-    let mn = input.iter().min().unwrap();
-    let mx = input.iter().max().unwrap();
+// Cost for a single crab at l to move to r; must be convex in r
+enum Cost<'a> {
+    Linear,
+    Triangular,
+    Quadratic,
+    Custom(&'a dyn Fn(i64, i64) -> i64),
+}
+
+impl Cost<'_> {
+    fn eval(&self, l: i64, r: i64) -> i64 {
+        match self {
+            Cost::Linear => cost_p1(l, r),
+            Cost::Triangular => cost_p2(l, r),
+            Cost::Quadratic => cost_quadratic(l, r),
+            Cost::Custom(f) => f(l, r),
+        }
+    }
+
+    // Range known to contain an optimal position, clamped to the input range
+    fn bounds(&self, crabs: &[Crab]) -> (i64, i64) {
+        let mn = crabs.iter().map(|c| c.position).min().unwrap();
+        let mx = crabs.iter().map(|c| c.position).max().unwrap();
+        let total_weight = crabs.iter().map(|c| c.weight).sum::<i64>();
+        let mean =
+            crabs.iter().map(|c| c.weight * c.position).sum::<i64>() as f64 / total_weight as f64;
+        let (lo, hi) = match self {
+            Cost::Linear => {
+                let mut sorted = crabs.to_vec();
+                sorted.sort_unstable_by_key(|c| c.position);
+                // Weighted lower and upper median
+                let median = |past_half: &dyn Fn(i64) -> bool| {
+                    let mut acc = 0;
+                    sorted
+                        .iter()
+                        .find(|c| {
+                            acc += c.weight;
+                            past_half(acc)
+                        })
+                        .unwrap()
+                        .position
+                };
+                (
+                    median(&|acc| 2 * acc >= total_weight),
+                    median(&|acc| 2 * acc > total_weight),
+                )
+            }
+            // The optimum lies within half a step of the mean
+            Cost::Triangular => ((mean - 0.5).floor() as i64, (mean + 0.5).ceil() as i64),
+            Cost::Quadratic => (mean.floor() as i64, mean.ceil() as i64),
+            Cost::Custom(_) => (mn, mx),
+        };
+        (lo.max(mn), hi.min(mx))
+    }
+}
+
+// A weight of w counts as w crabs at the same position
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Crab {
+    position: i64,
+    weight: i64,
+}
+
+fn unweighted(input: &[i64]) -> Vec<Crab> {
+    input
+        .iter()
+        .map(|&position| Crab {
+            position,
+            weight: 1,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Alignment {
+    position: i64,
+    cost: i64,
+}
+
+fn total_cost(crabs: &[Crab], cost: &Cost, position: i64) -> i64 {
+    crabs
+        .iter()
+        .map(|c| c.weight * cost.eval(c.position, position))
+        .sum()
+}
+
+// The total cost is convex in the position, so the leftmost optimum is the
+// first position where the cost stops decreasing; binary search for it
+fn align_weighted(crabs: &[Crab], cost: &Cost) -> Option<Alignment> {
+    if crabs.iter().map(|c| c.weight).sum::<i64>() <= 0 {
+        return None;
+    }
+    let (mut lo, mut hi) = cost.bounds(crabs);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if total_cost(crabs, cost, mid + 1) >= total_cost(crabs, cost, mid) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    Some(Alignment {
+        position: lo,
+        cost: total_cost(crabs, cost, lo),
+    })
+}
+
+fn align(input: &[i64], cost: &Cost) -> Option<Alignment> {
+    align_weighted(&unweighted(input), cost)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RallyPlan {
+    cost: i64,
+    // Sorted by position, each with the cost of the crabs it serves
+    rallies: Vec<Alignment>,
+}
 
-    let mut min_cost = None;
+// k-median on a line: with a convex cost every rally point serves a
+// contiguous run of the sorted positions, so split the runs by dynamic
+// programming over (rally points used, positions covered). Crabs sharing a
+// position are merged first. For n distinct positions the table of single-run
+// alignments takes n^2 binary searches over up to n crabs each, so
+// O(n^3 log range), and the DP over it is a further O(k * n^2).
+fn rally_points(crabs: &[Crab], cost: &Cost, k: usize) -> Option<RallyPlan> {
+    let mut merged: Vec<Crab> = Vec::new();
+    let mut sorted: Vec<Crab> = crabs.iter().filter(|c| c.weight > 0).copied().collect();
+    sorted.sort_unstable_by_key(|c| c.position);
+    for c in sorted {
+        match merged.last_mut() {
+            Some(last) if last.position == c.position => last.weight += c.weight,
+            _ => merged.push(c),
+        }
+    }
+    let n = merged.len();
+    let k = k.min(n);
+    if k == 0 {
+        return None;
+    }
+
+    // run[i][j]: best single rally point for merged[i..=j]
+    let run: Vec<Vec<Alignment>> = (0..n)
+        .map(|i| {
+            (i..n)
+                .map(|j| align_weighted(&merged[i..=j], cost).unwrap())
+                .collect()
+        })
+        .collect();
+    let run = |i: usize, j: usize| run[i][j - i];
 
-    for a in *mn..*mx + 1 {
-        let cost = input.iter().map(|v| cost_p2(*v, a)).sum::<i64>();
-        min_cost = min_cost
-            .map(|min| if cost < min { cost } else { min })
-            .or(Some(cost));
+    // best[r][j]: cost of covering merged[..j] with r rally points, and where
+    // the last run starts
+    let mut best = vec![vec![None; n + 1]; k + 1];
+    best[0][0] = Some((0, 0));
+    for r in 1..=k {
+        for j in r..=n {
+            best[r][j] = (r - 1..j)
+                .filter_map(|i| best[r - 1][i].map(|(c, _)| (c + run(i, j - 1).cost, i)))
+                .min();
+        }
     }
-    min_cost.unwrap()
+
+    let mut rallies = Vec::with_capacity(k);
+    let mut j = n;
+    for r in (1..=k).rev() {
+        let (_, i) = best[r][j].unwrap();
+        rallies.push(run(i, j - 1));
+        j = i;
+    }
+    rallies.reverse();
+    Some(RallyPlan {
+        cost: best[k][n].unwrap().0,
+        rallies,
+    })
+}
+
+fn part1(input: &[i64]) -> i64 {
+    align(input, &Cost::Linear).unwrap().cost
+}
+
+fn part2(input: &[i64]) -> i64 {
+    align(input, &Cost::Triangular).unwrap().cost
 }
 
 pub fn main() -> std::io::Result<()> {
@@ -70,6 +237,97 @@ fn task() {
     assert_eq!(part2(&input), 96678050);
 }
 
+#[test]
+fn alignment() {
+    let input = parse_input("16,1,2,0,4,2,7,1,2,14");
+    assert_eq!(
+        align(&input, &Cost::Linear),
+        Some(Alignment {
+            position: 2,
+            cost: 37
+        })
+    );
+    assert_eq!(
+        align(&input, &Cost::Triangular),
+        Some(Alignment {
+            position: 5,
+            cost: 168
+        })
+    );
+    assert_eq!(align(&[], &Cost::Linear), None);
+
+    // Every cost function agrees with trying every position
+    let cubic = |l: i64, r: i64| (l - r).abs().pow(3);
+    let task = parse_input(&input1().unwrap());
+    for input in [&input, &task] {
+        let crabs = unweighted(input);
+        for cost in [
+            Cost::Linear,
+            Cost::Triangular,
+            Cost::Quadratic,
+            Cost::Custom(&cubic),
+        ] {
+            let mn = *input.iter().min().unwrap();
+            let mx = *input.iter().max().unwrap();
+            let best = (mn..=mx)
+                .map(|p| total_cost(&crabs, &cost, p))
+                .min()
+                .unwrap();
+            assert_eq!(align(input, &cost).unwrap().cost, best);
+        }
+    }
+}
+
+#[test]
+fn weighted_rallies() {
+    let input = parse_input("16,1,2,0,4,2,7,1,2,14");
+    let crabs = unweighted(&input);
+
+    // Weights act like repeated crabs
+    let mut heavy = crabs.clone();
+    heavy[0].weight = 5;
+    let mut repeated = input.clone();
+    repeated.extend([16; 4]);
+    for cost in [Cost::Linear, Cost::Triangular, Cost::Quadratic] {
+        assert_eq!(align_weighted(&heavy, &cost), align(&repeated, &cost));
+    }
+
+    let single = rally_points(&crabs, &Cost::Linear, 1).unwrap();
+    assert_eq!(single.rallies, vec![align(&input, &Cost::Linear).unwrap()]);
+
+    // Two rally points checked against every pair of positions, with each
+    // crab going to its cheaper one
+    let mn = *input.iter().min().unwrap();
+    let mx = *input.iter().max().unwrap();
+    for cost in [Cost::Linear, Cost::Triangular] {
+        for crabs in [&crabs, &heavy] {
+            let brute = (mn..=mx)
+                .flat_map(|a| (a..=mx).map(move |b| (a, b)))
+                .map(|(a, b)| {
+                    crabs
+                        .iter()
+                        .map(|c| c.weight * cost.eval(c.position, a).min(cost.eval(c.position, b)))
+                        .sum::<i64>()
+                })
+                .min()
+                .unwrap();
+            let plan = rally_points(crabs, &cost, 2).unwrap();
+            assert_eq!(plan.cost, brute);
+            assert_eq!(plan.cost, plan.rallies.iter().map(|a| a.cost).sum::<i64>());
+        }
+    }
+
+    let plan = rally_points(&crabs, &Cost::Triangular, 2).unwrap();
+    assert_eq!(plan.rallies.len(), 2);
+    assert!(plan.rallies[0].position < plan.rallies[1].position);
+    // One rally point per distinct position costs nothing
+    assert_eq!(
+        rally_points(&crabs, &Cost::Triangular, 100).unwrap().cost,
+        0
+    );
+    assert_eq!(rally_points(&crabs, &Cost::Linear, 0), None);
+}
+
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {
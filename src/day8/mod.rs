@@ -1,7 +1,7 @@
 extern crate test;
-use itertools::zip;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::BTreeSet;
+use std::fmt;
 use std::fs;
 
 #[cfg(test)]
@@ -11,7 +11,8 @@ fn input1() -> std::io::Result<String> {
     fs::read_to_string("./src/day8/input.txt")
 }
 
-type Disp = ([String; 10], [String; 4]);
+// Observed patterns (ideally all ten) and the output digits
+type Disp = (Vec<String>, Vec<String>);
 
 fn parse_input(input: &str) -> Vec<Disp> {
     input
@@ -23,15 +24,8 @@ fn parse_input(input: &str) -> Vec<Disp> {
                 unique_patterns
                     .split_whitespace()
                     .map(|v| v.to_string())
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .expect("Expected output to fit into size 10 array"),
-                output
-                    .split_whitespace()
-                    .map(|v| v.to_string())
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .expect("Expected output to fit into size 4 array"),
+                    .collect(),
+                output.split_whitespace().map(|v| v.to_string()).collect(),
             )
         })
         .collect_vec()
@@ -47,7 +41,7 @@ fn classify_unique(pattern: &str) -> Result<char, &str> {
     }
 }
 
-fn part1(input: &Vec<Disp>) -> usize {
+fn part1(input: &[Disp]) -> usize {
     input
         .iter()
         .flat_map(|(_, output)| output)
@@ -56,115 +50,287 @@ fn part1(input: &Vec<Disp>) -> usize {
         .count()
 }
 
-fn to_bitmask(signal: &str) -> u8 {
-    //  "abcdefg"  -> 1111111
-    // "abde" -> 0011011
+// Glyphs as segment masks, segment i being the i-th letter from 'a'
+struct GlyphTable {
+    segments: usize,
+    glyphs: Vec<(char, u32)>,
+}
+
+impl GlyphTable {
+    fn new(segments: usize, glyphs: &[(char, &str)]) -> Self {
+        let glyphs = glyphs
+            .iter()
+            .map(|(c, segs)| (*c, segs.bytes().fold(0, |acc, v| acc | 1 << (v - b'a'))))
+            .collect();
+        GlyphTable { segments, glyphs }
+    }
+
+    fn seven_segment() -> Self {
+        GlyphTable::new(
+            7,
+            &[
+                ('0', "abcefg"),
+                ('1', "cf"),
+                ('2', "acdeg"),
+                ('3', "acdfg"),
+                ('4', "bcdf"),
+                ('5', "abdfg"),
+                ('6', "abdefg"),
+                ('7', "acf"),
+                ('8', "abcdefg"),
+                ('9', "abcdfg"),
+            ],
+        )
+    }
+
+    fn lookup(&self, segments: u32) -> Option<char> {
+        self.glyphs
+            .iter()
+            .find(|(_, g)| *g == segments)
+            .map(|(c, _)| *c)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum DecodeError {
+    UnknownWire(String),
+    Inconsistent,
+    Ambiguous,
+    UnknownGlyph(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownWire(p) => write!(f, "pattern {:?} uses a wire outside the display", p),
+            Self::Inconsistent => write!(f, "no wiring is consistent with the patterns"),
+            Self::Ambiguous => write!(f, "more than one wiring is consistent with the patterns"),
+            Self::UnknownGlyph(p) => write!(f, "output {:?} does not decode to a glyph", p),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn to_bitmask(table: &GlyphTable, signal: &str) -> Result<u32, DecodeError> {
     signal
         .bytes()
-        .map(|v| 1 << v - b'a')
-        .fold(0, |acc, v| acc + v)
+        .try_fold(0, |acc, v| match v.checked_sub(b'a') {
+            Some(i) if (i as usize) < table.segments => Ok(acc | 1 << i),
+            _ => Err(DecodeError::UnknownWire(signal.to_string())),
+        })
 }
 
-fn nth(v: &u8, bits: usize, n: usize) -> u8 {
-    (v & (1 << bits - 1) >> n) >> (bits - 1 - n)
-}
+// wiring[wire] = segment it drives
+type Wiring = Vec<usize>;
 
-fn sum_nth_bit(bits_slice: &[u8], n: usize, bits: usize) -> usize {
-    bits_slice
+fn apply(wiring: &[usize], wires: u32) -> u32 {
+    wiring
         .iter()
-        .fold(0, |acc, v| acc + nth(v, bits, n) as usize)
+        .enumerate()
+        .filter(|(w, _)| wires & 1 << w != 0)
+        .fold(0, |acc, (_, s)| acc | 1 << s)
 }
 
-fn bit_n(n: usize) -> u8 {
-    1 << (7 - n)
+struct Solver<'a> {
+    table: &'a GlyphTable,
+    patterns: &'a [u32],
+    candidates: Vec<u32>,
+    wiring: Vec<Option<usize>>,
+    found: Vec<Wiring>,
+    limit: usize,
 }
 
-fn neg_7(n: u8) -> u8 {
-    !n & u8::MAX >> 1
+impl<'a> Solver<'a> {
+    fn new(table: &'a GlyphTable, patterns: &'a [u32], limit: usize) -> Self {
+        // A wire lit in a pattern can only drive a segment of a glyph that
+        // has as many segments as the pattern has wires
+        let mut candidates = vec![(1 << table.segments) - 1; table.segments];
+        for p in patterns {
+            let allowed = table
+                .glyphs
+                .iter()
+                .filter(|(_, g)| g.count_ones() == p.count_ones())
+                .fold(0, |acc, (_, g)| acc | g);
+            for (w, c) in candidates.iter_mut().enumerate() {
+                if p & 1 << w != 0 {
+                    *c &= allowed;
+                }
+            }
+        }
+        Solver {
+            table,
+            patterns,
+            candidates,
+            wiring: vec![None; table.segments],
+            found: Vec::new(),
+            limit,
+        }
+    }
+
+    // Every pattern can still become some glyph: the segments of its assigned
+    // wires are in the glyph and the segments of other assigned wires are not
+    fn consistent(&self) -> bool {
+        self.patterns.iter().all(|p| {
+            let (mut inside, mut outside) = (0, 0);
+            for (w, s) in self.wiring.iter().enumerate() {
+                if let Some(s) = s {
+                    match p & 1 << w != 0 {
+                        true => inside |= 1 << s,
+                        false => outside |= 1 << s,
+                    }
+                }
+            }
+            self.table.glyphs.iter().any(|(_, g)| {
+                g.count_ones() == p.count_ones() && inside & !g == 0 && outside & g == 0
+            })
+        })
+    }
+
+    fn search(&mut self, order: &[usize], used: u32) {
+        if self.found.len() >= self.limit {
+            return;
+        }
+        let (wire, rest) = match order.split_first() {
+            Some(x) => x,
+            None => {
+                self.found
+                    .push(self.wiring.iter().map(|s| s.unwrap()).collect());
+                return;
+            }
+        };
+        let options = self.candidates[*wire] & !used;
+        for s in (0..self.table.segments).filter(|s| options & 1 << s != 0) {
+            self.wiring[*wire] = Some(s);
+            if self.consistent() {
+                self.search(rest, used | 1 << s);
+            }
+        }
+        self.wiring[*wire] = None;
+    }
+
+    fn solve(mut self) -> Vec<Wiring> {
+        let mut order: Vec<usize> = (0..self.table.segments).collect();
+        order.sort_by_key(|w| self.candidates[*w].count_ones());
+        self.search(&order, 0);
+        self.found
+    }
 }
 
-fn bit_union<'a>(vals: impl Iterator<Item = &'a u8>) -> u8 {
-    vals.fold(0u8, |acc, v| acc | v)
+// Up to limit wirings that map every pattern onto a glyph
+fn wirings(table: &GlyphTable, patterns: &[u32], limit: usize) -> Vec<Wiring> {
+    Solver::new(table, patterns, limit).solve()
 }
 
-fn build_classify_map(segment_map: &HashMap<char, u8>) -> HashMap<u8, char> {
-    let mut classify_map = HashMap::new();
-    for (seg, n) in zip(
-        [
-            "abcefg", "cf", "acdeg", "acdfg", "bcdf", "abdfg", "abdefg", "acf", "abcdefg", "abcdfg",
-        ],
-        "0123456789".chars(),
-    ) {
-        classify_map.insert(
-            bit_union(seg.chars().map(|v| segment_map.get(&v).unwrap())),
-            n,
-        );
+fn solve(table: &GlyphTable, patterns: &[u32]) -> Result<Wiring, DecodeError> {
+    let mut found = wirings(table, patterns, 2);
+    match found.len() {
+        0 => Err(DecodeError::Inconsistent),
+        1 => Ok(found.pop().unwrap()),
+        _ => Err(DecodeError::Ambiguous),
     }
-    classify_map
 }
 
-fn part2(input: &Vec<Disp>) -> i32 {
-    let mut sum = 0;
-
-    for (signal, output) in input {
-        let bits: Vec<_> = signal.iter().map(|v| to_bitmask(v)).collect();
-        let output_bits: Vec<_> = output.iter().map(|v| to_bitmask(v)).collect();
+fn decode<S: AsRef<str>>(
+    table: &GlyphTable,
+    patterns: &[S],
+    output: &[S],
+) -> Result<String, DecodeError> {
+    let masks = patterns
+        .iter()
+        .map(|p| to_bitmask(table, p.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let wiring = solve(table, &masks)?;
+    output
+        .iter()
+        .map(|o| {
+            let o = o.as_ref();
+            table
+                .lookup(apply(&wiring, to_bitmask(table, o)?))
+                .ok_or_else(|| DecodeError::UnknownGlyph(o.to_string()))
+        })
+        .collect()
+}
 
-        let mut number_map = HashMap::new();
-        let mut segment_map = HashMap::new();
+#[derive(Debug, PartialEq)]
+struct Diagnosis {
+    wirings: Vec<Wiring>,
+    // More wirings fit the evidence than the limit allowed
+    truncated: bool,
+    // Glyphs each output could be under any of the kept wirings
+    digits: Vec<BTreeSet<char>>,
+}
 
-        for b in &bits {
-            match b.count_ones() {
-                2 => number_map.insert(1, *b),
-                3 => number_map.insert(7, *b),
-                4 => number_map.insert(4, *b),
-                7 => number_map.insert(8, *b),
+impl Diagnosis {
+    fn value(&self) -> Option<String> {
+        self.digits
+            .iter()
+            .map(|d| match d.len() {
+                1 => d.iter().next().copied(),
                 _ => None,
-            };
-        }
+            })
+            .collect()
+    }
+}
 
-        segment_map.insert(
-            'a',
-            number_map.get(&7).unwrap() ^ number_map.get(&1).unwrap(),
-        );
+// Unlike decode this never gives up on missing patterns: the outputs count as
+// evidence too, and up to limit wirings consistent with all of it are kept.
+// Sparse evidence on wide tables fits factorially many wirings, hence the limit.
+fn diagnose<S: AsRef<str>>(
+    table: &GlyphTable,
+    patterns: &[S],
+    output: &[S],
+    limit: usize,
+) -> Result<Diagnosis, DecodeError> {
+    let output = output
+        .iter()
+        .map(|o| to_bitmask(table, o.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut evidence = patterns
+        .iter()
+        .map(|p| to_bitmask(table, p.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    evidence.extend(&output);
+    evidence.sort_unstable();
+    evidence.dedup();
 
-        for pos in 1..8 {
-            match sum_nth_bit(&bits, pos, 8) {
-                4 => segment_map.insert('e', bit_n(pos)),
-                6 => segment_map.insert('b', bit_n(pos)),
-                9 => segment_map.insert('f', bit_n(pos)),
-                _ => None,
-            };
-        }
+    let mut wirings = wirings(table, &evidence, limit.saturating_add(1));
+    let truncated = wirings.len() > limit;
+    wirings.truncate(limit);
+    let digits = output
+        .iter()
+        .map(|o| {
+            wirings
+                .iter()
+                .filter_map(|w| table.lookup(apply(w, *o)))
+                .collect()
+        })
+        .collect();
+    Ok(Diagnosis {
+        wirings,
+        truncated,
+        digits,
+    })
+}
 
-        segment_map.insert(
-            'd',
-            number_map.get(&4).unwrap()
-                ^ number_map.get(&1).unwrap()
-                ^ segment_map.get(&'b').unwrap(),
-        );
-        segment_map.insert(
-            'c',
-            segment_map.get(&'f').unwrap() ^ number_map.get(&1).unwrap(),
-        );
-        segment_map.insert('g', neg_7(bit_union(segment_map.values())));
-
-        let classify_map = build_classify_map(&segment_map);
-
-        sum += output_bits
-            .iter()
-            .map(|v| *classify_map.get(&v).unwrap())
-            .collect::<String>()
-            .parse::<i32>()
-            .expect("could not parse result as number");
-    }
-    sum
+fn part2(input: &[Disp]) -> Result<i32, DecodeError> {
+    let table = GlyphTable::seven_segment();
+    input
+        .iter()
+        .map(|(signal, output)| {
+            let digits = decode(&table, signal, output)?;
+            Ok(digits
+                .parse::<i32>()
+                .expect("could not parse result as number"))
+        })
+        .sum()
 }
 
 pub fn main() -> std::io::Result<()> {
     let input = parse_input(&input1()?);
     println!("{:?}", part1(&input));
-    println!("{:?}", part2(&input));
+    let sum = part2(&input).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    println!("{:?}", sum);
     Ok(())
 }
 
@@ -183,14 +349,137 @@ fn example() {
     gcafb gcf dcaebfg ecagb gf abcdeg gaef cafbge fdbac fegbdc | fgae cfgab fg bagce";
     let input = parse_input(input);
     assert_eq!(part1(&input), 26);
-    assert_eq!(part2(&input), 61229);
+    assert_eq!(part2(&input), Ok(61229));
 }
 
 #[test]
 fn task() {
     let input = parse_input(&input1().unwrap());
     assert_eq!(part1(&input), 449);
-    assert_eq!(part2(&input), 968175);
+    assert_eq!(part2(&input), Ok(968175));
+}
+
+#[test]
+fn hex_display() {
+    let table = GlyphTable::new(
+        7,
+        &[
+            ('0', "abcefg"),
+            ('1', "cf"),
+            ('2', "acdeg"),
+            ('3', "acdfg"),
+            ('4', "bcdf"),
+            ('5', "abdfg"),
+            ('6', "abdefg"),
+            ('7', "acf"),
+            ('8', "abcdefg"),
+            ('9', "abcdfg"),
+            ('A', "abcdef"),
+            ('B', "bdefg"),
+            ('C', "abeg"),
+            ('D', "cdefg"),
+            ('E', "abdeg"),
+            ('F', "abde"),
+        ],
+    );
+    // Segment s is driven by wire scramble[s]
+    let scramble = "dgbfcae".as_bytes();
+    let wire = |glyph: char| -> String {
+        let (_, mask) = table.glyphs.iter().find(|(c, _)| *c == glyph).unwrap();
+        (0..7)
+            .filter(|s| mask & 1 << s != 0)
+            .map(|s| scramble[s] as char)
+            .collect()
+    };
+    let patterns: Vec<String> = "0123456789ABCDEF".chars().map(wire).collect();
+    let output: Vec<String> = "C0FFEE".chars().map(wire).collect();
+    assert_eq!(decode(&table, &patterns, &output), Ok("C0FFEE".to_string()));
+
+    // Only seeing 1 and 7 leaves most of the wiring open
+    let few = vec![wire('1'), wire('7')];
+    assert_eq!(
+        decode(&table, &few, &[wire('1')]),
+        Err(DecodeError::Ambiguous)
+    );
+    let mut broken = patterns.clone();
+    broken[1] = "ab".to_string();
+    broken[7] = "abc".to_string();
+    broken[4] = "abcd".to_string();
+    assert_eq!(
+        decode(&table, &broken, &output),
+        Err(DecodeError::Inconsistent)
+    );
+    assert_eq!(
+        decode(&table, &patterns, &["xyz".to_string()]),
+        Err(DecodeError::UnknownWire("xyz".to_string()))
+    );
+
+    // Eight segments: the digits plus a decimal point
+    let mut glyphs = GlyphTable::seven_segment().glyphs;
+    glyphs.push(('.', 1 << 7));
+    let table = GlyphTable {
+        segments: 8,
+        glyphs,
+    };
+    // Wires c and g swapped
+    let patterns = [
+        "abcefg", "gf", "acdeg", "acdfg", "bdfg", "abcdf", "abcdef", "afg", "abcdefg", "abcdfg",
+        "h",
+    ];
+    let decoded = decode(&table, &patterns, &["gf", "h", "afg"]);
+    assert_eq!(decoded, Ok("1.7".to_string()));
+}
+
+#[test]
+fn partial_entries() {
+    let table = GlyphTable::seven_segment();
+    let (patterns, output) = parse_input(
+        "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf",
+    )
+    .pop()
+    .unwrap();
+
+    // Seven wires can only be permuted 5040 ways, so nothing is cut off
+    let limit = 5040;
+    let full = diagnose(&table, &patterns, &output, limit).unwrap();
+    assert_eq!(full.wirings.len(), 1);
+    assert!(!full.truncated);
+    assert_eq!(full.value(), Some("5353".to_string()));
+
+    // Dropping patterns widens the candidates, but the answer always stays
+    // among them
+    for keep in 0..patterns.len() {
+        let partial = diagnose(&table, &patterns[..keep], &output, limit).unwrap();
+        assert!(!partial.truncated);
+        assert!(partial.wirings.contains(&full.wirings[0]));
+        for (digit, possible) in "5353".chars().zip(&partial.digits) {
+            assert!(possible.contains(&digit));
+        }
+    }
+
+    // With only 1 seen, a five segment output sharing one wire with it is
+    // either 2 or 5
+    let partial = diagnose(&table, &["ab"], &["cdfeb", "ab"], limit).unwrap();
+    assert_eq!(partial.digits[0], ['2', '5'].into_iter().collect());
+    assert_eq!(partial.digits[1], ['1'].into_iter().collect());
+    assert_eq!(partial.value(), None);
+
+    // Outputs no wiring can explain leave nothing
+    let noisy = diagnose(
+        &table,
+        &patterns,
+        &["abcdefg".to_string(), "ag".to_string()],
+        limit,
+    )
+    .unwrap();
+    assert!(noisy.wirings.is_empty());
+    assert!(noisy.digits.iter().all(|d| d.is_empty()));
+
+    // A lone 8 is consistent with every permutation
+    let capped = diagnose(&table, &["abcdefg"], &["abcdefg"], 100).unwrap();
+    assert_eq!(capped.wirings.len(), 100);
+    assert!(capped.truncated);
+    assert_eq!(capped.digits[0], ['8'].into_iter().collect());
 }
 
 #[bench]
@@ -198,6 +487,6 @@ fn task_bench(b: &mut Bencher) {
     b.iter(|| {
         let input = parse_input(&input1().unwrap());
         part1(&input);
-        part2(&input);
+        part2(&input).unwrap();
     })
 }
@@ -65,43 +65,223 @@ fn part1(input: &Floor) -> u32 {
         .sum()
 }
 
-fn part2(input: &Floor) -> u32 {
-    let floor = pad(input, 9);
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Connectivity {
+    Four,
+    Eight,
+}
 
-    let mask_vec = local_min_mask(floor);
+type Index = (usize, usize);
 
-    let indices: Vec<_> = zip(input.indexed_iter(), mask_vec.iter())
-        .filter(|(_, m)| **m)
-        .map(|((idx, _), _)| idx)
-        .collect();
+fn neighbours(
+    (r, c): Index,
+    (n_rows, n_cols): Index,
+    connectivity: Connectivity,
+) -> impl Iterator<Item = Index> {
+    const FOUR: &[(isize, isize)] = &[(1, 0), (-1, 0), (0, 1), (0, -1)];
+    const EIGHT: &[(isize, isize)] = &[
+        (1, 0),
+        (-1, 0),
+        (0, 1),
+        (0, -1),
+        (1, 1),
+        (1, -1),
+        (-1, 1),
+        (-1, -1),
+    ];
+    let dirs = match connectivity {
+        Connectivity::Four => FOUR,
+        Connectivity::Eight => EIGHT,
+    };
+    dirs.iter().filter_map(move |(dr, dc)| {
+        let next = (r.checked_add_signed(*dr)?, c.checked_add_signed(*dc)?);
+        (next.0 < n_rows && next.1 < n_cols).then_some(next)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Basin {
+    size: usize,
+    // Lowest cell, the first in row-major order on ties
+    low_point: Index,
+    // Top left and bottom right corners, inclusive
+    bounding_box: (Index, Index),
+}
+
+struct Labeling {
+    // 0 for walls, otherwise basin index + 1
+    labels: Array2<usize>,
+    basins: Vec<Basin>,
+}
+
+// Basins are the connected regions of cells lower than wall
+fn label_basins(floor: &Floor, wall: u32, connectivity: Connectivity) -> Labeling {
+    let mut labels = Array2::<usize>::zeros(floor.raw_dim());
+    let mut basins = Vec::new();
+    for (start, height) in floor.indexed_iter() {
+        if *height >= wall || labels[start] != 0 {
+            continue;
+        }
+        let label = basins.len() + 1;
+        let mut basin = Basin {
+            size: 0,
+            low_point: start,
+            bounding_box: (start, start),
+        };
+        labels[start] = label;
+        let mut to_visit = VecDeque::from([start]);
+        while let Some(front) = to_visit.pop_front() {
+            basin.size += 1;
+            if floor[front] < floor[basin.low_point]
+                || (floor[front] == floor[basin.low_point] && front < basin.low_point)
+            {
+                basin.low_point = front;
+            }
+            let ((r0, c0), (r1, c1)) = basin.bounding_box;
+            basin.bounding_box = (
+                (r0.min(front.0), c0.min(front.1)),
+                (r1.max(front.0), c1.max(front.1)),
+            );
+            for next in neighbours(front, floor.dim(), connectivity) {
+                if floor[next] < wall && labels[next] == 0 {
+                    labels[next] = label;
+                    to_visit.push_back(next);
+                }
+            }
+        }
+        basins.push(basin);
+    }
+    Labeling { labels, basins }
+}
+
+fn part2(input: &Floor) -> usize {
+    let labeling = label_basins(input, 9, Connectivity::Four);
+    labeling
+        .basins
+        .iter()
+        .map(|b| b.size)
+        .sorted_unstable()
+        .rev()
+        .take(3)
+        .product()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Catchment {
+    // First cell in row-major order of the minimum (a single cell unless the
+    // minimum is a plateau)
+    low_point: Index,
+    height: u32,
+    area: usize,
+}
+
+struct Watershed {
+    // 0 for walls, otherwise catchment index + 1
+    labels: Array2<usize>,
+    // Ordered by low point
+    catchments: Vec<Catchment>,
+}
+
+impl Watershed {
+    fn total_risk(&self) -> u32 {
+        self.catchments.iter().map(|c| c.height + 1).sum()
+    }
+}
+
+// Every cell drains to its lowest strictly lower neighbour, the first one in
+// row-major order on ties. Flat regions drain through whichever of their
+// cells with a lower neighbour is nearest, and a flat region with no way down
+// is a single minimum. Cells at or above wall, if given, are left out.
+fn watershed(floor: &Floor, wall: Option<u32>, connectivity: Connectivity) -> Watershed {
+    let open = |idx: Index| wall.is_none_or(|w| floor[idx] < w);
+    let dim = floor.dim();
+
+    // Where each cell drains to; minima point at themselves
+    let mut downstream = Array2::<Option<Index>>::from_elem(dim, None);
+    let mut plateau_seen = Array2::<bool>::from_elem(dim, false);
+    for (start, height) in floor.indexed_iter() {
+        if !open(start) || plateau_seen[start] {
+            continue;
+        }
+        let mut plateau = vec![start];
+        plateau_seen[start] = true;
+        let mut i = 0;
+        while i < plateau.len() {
+            for next in neighbours(plateau[i], dim, connectivity) {
+                if open(next) && floor[next] == *height && !plateau_seen[next] {
+                    plateau_seen[next] = true;
+                    plateau.push(next);
+                }
+            }
+            i += 1;
+        }
+        plateau.sort_unstable();
 
-    let mut basin_id = 0;
-    let mut visited = Array2::<usize>::zeros(input.raw_dim());
-    for i in indices {
-        basin_id += 1;
-        let mut to_visit = VecDeque::from([i]);
+        let mut to_visit = VecDeque::new();
+        for &cell in &plateau {
+            let lowest = neighbours(cell, dim, connectivity)
+                .filter(|&n| open(n) && floor[n] < *height)
+                .min_by_key(|&n| (floor[n], n));
+            if let Some(lowest) = lowest {
+                downstream[cell] = Some(lowest);
+                to_visit.push_back(cell);
+            }
+        }
+        if to_visit.is_empty() {
+            for &cell in &plateau {
+                downstream[cell] = Some(plateau[0]);
+            }
+            continue;
+        }
         while let Some(front) = to_visit.pop_front() {
-            const CARDINALS: &[(usize, usize); 4] =
-                &[(1, 0), (usize::MAX, 0), (0, 1), (0, usize::MAX)];
-            for dir in CARDINALS {
-                let next = (front.0.wrapping_add(dir.0), front.1.wrapping_add(dir.1));
-                if let Some(0..=8) = input.get(next) {
-                    if visited[next] == 0 {
-                        to_visit.push_back(next);
-                        visited[next] = basin_id;
-                    }
+            for next in neighbours(front, dim, connectivity) {
+                if open(next) && floor[next] == *height && downstream[next].is_none() {
+                    downstream[next] = Some(front);
+                    to_visit.push_back(next);
                 }
             }
         }
     }
 
-    let mut counts = vec![0; basin_id + 1];
-    for v in visited.iter() {
-        counts[*v] += 1
+    let mut sinks = Array2::<Option<Index>>::from_elem(dim, None);
+    for (start, _) in floor.indexed_iter() {
+        if !open(start) || sinks[start].is_some() {
+            continue;
+        }
+        let mut path = vec![start];
+        let sink = loop {
+            let cell = *path.last().unwrap();
+            if let Some(sink) = sinks[cell] {
+                break sink;
+            }
+            match downstream[cell].unwrap() {
+                next if next == cell => break cell,
+                next => path.push(next),
+            }
+        };
+        for cell in path {
+            sinks[cell] = Some(sink);
+        }
     }
 
-    counts.sort_unstable();
-    counts.iter().rev().skip(1).take(3).product()
+    let low_points: Vec<Index> = sinks.iter().flatten().copied().sorted().dedup().collect();
+    let mut catchments: Vec<Catchment> = low_points
+        .iter()
+        .map(|&low_point| Catchment {
+            low_point,
+            height: floor[low_point],
+            area: 0,
+        })
+        .collect();
+    let labels = sinks.map(|sink| match sink {
+        Some(sink) => {
+            let i = low_points.binary_search(sink).unwrap();
+            catchments[i].area += 1;
+            i + 1
+        }
+        None => 0,
+    });
+    Watershed { labels, catchments }
 }
 
 pub fn main() -> std::io::Result<()> {
@@ -130,6 +310,119 @@ fn task() {
     assert_eq!(part2(&input), 1122700);
 }
 
+#[test]
+fn labeling() {
+    let input = parse_input(
+        "2199943210
+    3987894921
+    9856789892
+    8767896789
+    9899965678",
+    );
+    let labeling = label_basins(&input, 9, Connectivity::Four);
+    assert_eq!(labeling.basins.len(), 4);
+    assert_eq!(
+        labeling.basins[0],
+        Basin {
+            size: 3,
+            low_point: (0, 1),
+            bounding_box: ((0, 0), (1, 1))
+        }
+    );
+    assert_eq!(
+        labeling.basins[1],
+        Basin {
+            size: 9,
+            low_point: (0, 9),
+            bounding_box: ((0, 5), (2, 9))
+        }
+    );
+    assert_eq!(labeling.labels[[0, 0]], 1);
+    assert_eq!(labeling.labels[[0, 2]], 0);
+    assert_eq!(labeling.labels[[4, 9]], 4);
+
+    // Diagonal steps leak through the walls and join all four basins
+    let labeling = label_basins(&input, 9, Connectivity::Eight);
+    assert_eq!(labeling.basins.len(), 1);
+    assert_eq!(labeling.basins[0].size, 3 + 9 + 14 + 9);
+
+    // Lower walls shrink the basins, a wall above everything merges them
+    let sizes = |wall| {
+        let labeling = label_basins(&input, wall, Connectivity::Four);
+        labeling.basins.iter().map(|b| b.size).collect::<Vec<_>>()
+    };
+    assert_eq!(sizes(8), vec![3, 9, 7, 6]);
+    assert_eq!(sizes(6), vec![3, 9, 1, 1]);
+    let labeling = label_basins(&input, 10, Connectivity::Four);
+    assert_eq!(labeling.basins.len(), 1);
+    assert_eq!(labeling.basins[0].size, 50);
+    assert_eq!(labeling.basins[0].low_point, (0, 9));
+}
+
+#[test]
+fn watersheds() {
+    let input = parse_input(
+        "2199943210
+    3987894921
+    9856789892
+    8767896789
+    9899965678",
+    );
+    // With the 9s as walls every basin has exactly one low point
+    let shed = watershed(&input, Some(9), Connectivity::Four);
+    assert_eq!(shed.total_risk(), 15);
+    assert_eq!(
+        shed.catchments.iter().map(|c| c.area).collect::<Vec<_>>(),
+        vec![3, 9, 14, 9]
+    );
+
+    let task = parse_input(&input1().unwrap());
+    let shed = watershed(&task, Some(9), Connectivity::Four);
+    assert_eq!(shed.total_risk(), part1(&task));
+    let product: usize = shed
+        .catchments
+        .iter()
+        .map(|c| c.area)
+        .sorted_unstable()
+        .rev()
+        .take(3)
+        .product();
+    assert_eq!(product, part2(&task));
+
+    // Without walls the 9s drain too
+    let shed = watershed(&input, None, Connectivity::Four);
+    assert_eq!(shed.catchments.iter().map(|c| c.area).sum::<usize>(), 50);
+    assert!(shed.labels.iter().all(|l| *l != 0));
+    assert_eq!(shed.total_risk(), 15);
+
+    // Plateaus: the flat 5s split between the 3 and the 1s by whichever way
+    // down is nearest, and the flat 1s are a single minimum
+    let input = parse_input(
+        "5555
+    5553
+    1155
+    1195",
+    );
+    let shed = watershed(&input, None, Connectivity::Four);
+    assert_eq!(
+        shed.catchments,
+        vec![
+            Catchment {
+                low_point: (1, 3),
+                height: 3,
+                area: 6
+            },
+            Catchment {
+                low_point: (2, 0),
+                height: 1,
+                area: 10
+            }
+        ]
+    );
+    assert_eq!(shed.labels[[0, 2]], 1);
+    assert_eq!(shed.labels[[0, 1]], 2);
+}
+
 #[bench]
 fn task_bench(b: &mut Bencher) {
     b.iter(|| {